@@ -0,0 +1,159 @@
+//! Progress bars of the transfers, with the log lines printed above them
+
+use std::{
+    io::IsTerminal,
+    sync::{Mutex, OnceLock},
+    time::Duration,
+};
+
+use colored::Colorize;
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+
+use deezer_to_spotify::logger::{log, LogCategory};
+use deezer_to_spotify::progress::Progress;
+
+struct Bars {
+    multi: MultiProgress,
+    /// Playlists done out of all the playlists of the transfer
+    overall: Option<ProgressBar>,
+    /// The playlist being matched, with its found and not found tracks
+    current: Option<(ProgressBar, u64, u64)>,
+}
+
+/// Only set when stdout is a terminal
+static BARS: OnceLock<Mutex<Bars>> = OnceLock::new();
+
+/// Draws the bars from now on if stdout is a terminal, everything is printed as plain lines otherwise
+pub fn init() {
+    if std::io::stdout().is_terminal() {
+        let _ = BARS.set(Mutex::new(Bars {
+            multi: MultiProgress::with_draw_target(ProgressDrawTarget::stdout()),
+            overall: None,
+            current: None,
+        }));
+    }
+}
+
+/// Prints the line above the bars
+pub fn print(line: &str) {
+    match BARS.get() {
+        Some(bars) => {
+            let _ = bars.lock().unwrap().multi.println(line);
+        }
+        None => println!("{line}"),
+    }
+}
+
+/// Prints the line to stderr, hiding the bars meanwhile
+pub fn eprint(line: &str) {
+    match BARS.get() {
+        Some(bars) => bars.lock().unwrap().multi.suspend(|| eprintln!("{line}")),
+        None => eprintln!("{line}"),
+    }
+}
+
+fn style(template: &str) -> ProgressStyle {
+    ProgressStyle::with_template(template)
+        .unwrap()
+        .progress_chars("=> ")
+}
+
+fn summary(matched: usize, unmatched: usize, error: Option<&str>) -> String {
+    match error {
+        Some(err) => format!("failed: {err}"),
+        None => format!("{matched} matched, {unmatched} unmatched"),
+    }
+}
+
+/// Updates the bars with a step of the transfer
+pub fn on_progress(progress: Progress) {
+    let Some(bars) = BARS.get() else {
+        // Failures are already logged by the transfer
+        if let Progress::Finished {
+            title,
+            matched,
+            unmatched,
+            error: None,
+        } = progress
+        {
+            log!(
+                "Transfer",
+                LogCategory::Info,
+                { playlist: title },
+                "\"{title}\": {}",
+                summary(matched, unmatched, None)
+            );
+        }
+        return;
+    };
+    let mut bars = bars.lock().unwrap();
+
+    match progress {
+        Progress::Started { playlists } => {
+            let bar = bars.multi.add(ProgressBar::new(playlists as u64));
+            bar.set_style(style("{prefix:.bold} [{bar:30.cyan/blue}] {pos}/{len}"));
+            bar.set_prefix("Playlists");
+            bars.overall = Some(bar);
+        }
+        Progress::Playlist { title } => {
+            let bar = ProgressBar::new_spinner();
+            let bar = match &bars.overall {
+                Some(overall) => bars.multi.insert_before(overall, bar),
+                None => bars.multi.add(bar),
+            };
+            bar.set_style(style("{spinner} {prefix:.bold} {msg}"));
+            bar.set_prefix(title.to_owned());
+            bar.set_message("matching...");
+            bar.enable_steady_tick(Duration::from_millis(100));
+            bars.current = Some((bar, 0, 0));
+        }
+        Progress::Searching { tracks } => {
+            if let Some((bar, _, _)) = &bars.current {
+                bar.set_style(style(
+                    "{spinner} {prefix:.bold} [{bar:30.green/red}] {pos}/{len} {msg}",
+                ));
+                bar.set_length(tracks as u64);
+            }
+        }
+        Progress::Searched { found } => {
+            if let Some((bar, found_count, missing_count)) = &mut bars.current {
+                if found {
+                    *found_count += 1;
+                } else {
+                    *missing_count += 1;
+                }
+                bar.inc(1);
+                bar.set_message(format!("{found_count} found, {missing_count} not found"));
+            }
+        }
+        Progress::Finished {
+            title,
+            matched,
+            unmatched,
+            error,
+        } => {
+            // Done playlists become lines above the bars, so only the current one has a bar
+            if let Some((bar, _, _)) = bars.current.take() {
+                bar.finish_and_clear();
+                bars.multi.remove(&bar);
+            }
+
+            let mark = match error {
+                Some(_) => "✘".red(),
+                None => "✔".green(),
+            };
+            let _ = bars.multi.println(format!(
+                "{mark} {} {}",
+                title.bold(),
+                summary(matched, unmatched, error)
+            ));
+
+            if let Some(overall) = &bars.overall {
+                overall.inc(1);
+                if Some(overall.position()) == overall.length() {
+                    overall.finish();
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,272 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::deezer::DeezerTrack;
+use crate::logger::{log, LogCategory};
+use crate::spotify::SpotifyTrack;
+use crate::unix_time;
+
+const CACHE_VERSION: u32 = 1;
+
+/// A cached match
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CacheEntry {
+    /// The Spotify track the Deezer track was matched to
+    pub track: SpotifyTrack,
+    /// Score of the match, between 0 and 1
+    pub confidence: f64,
+    /// Unix timestamp (seconds) of when the match was made
+    pub cached_at: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct CacheFile {
+    version: u32,
+    /// Keyed by `deezer:<id>` and `isrc:<isrc>`
+    entries: BTreeMap<String, CacheEntry>,
+}
+
+/// Spotify matches of previous runs, so tracks don't get searched again
+#[derive(Debug)]
+pub struct MatchCache {
+    path: PathBuf,
+    entries: Mutex<BTreeMap<String, CacheEntry>>,
+    /// When disabled, nothing is read from nor written to the cache
+    enabled: bool,
+}
+
+fn keys(track: &DeezerTrack) -> Vec<String> {
+    let mut keys = Vec::new();
+
+    if track.id != 0 {
+        keys.push(format!("deezer:{}", track.id));
+    }
+    if let Some(isrc) = &track.isrc {
+        keys.push(format!("isrc:{isrc}"));
+    }
+
+    keys
+}
+
+impl Default for MatchCache {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::new(),
+            entries: Mutex::new(BTreeMap::new()),
+            enabled: false,
+        }
+    }
+}
+
+impl MatchCache {
+    /// `~/.cache/deezer_to_spotify/matches.json` on Linux, the platform equivalent elsewhere
+    pub fn default_path() -> PathBuf {
+        dirs::cache_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("deezer_to_spotify")
+            .join("matches.json")
+    }
+
+    /// Loads the cache, a missing or unreadable file means an empty cache
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let mut cache = Self {
+            path: path.to_owned(),
+            enabled: true,
+            ..Default::default()
+        };
+
+        if !path.exists() {
+            return Ok(cache);
+        }
+
+        let json = std::fs::read_to_string(path)
+            .map_err(|err| format!("Failed to read the match cache {}: {err}", path.display()))?;
+
+        // A cache is only a shortcut, a broken one shouldn't stop the transfers
+        let file: CacheFile = match serde_json::from_str(&json) {
+            Ok(file) => file,
+            Err(err) => {
+                log!(
+                    "Cache",
+                    LogCategory::Warn,
+                    "Ignoring the match cache {}, it can't be parsed: {err}",
+                    path.display()
+                );
+                return Ok(cache);
+            }
+        };
+
+        if file.version != CACHE_VERSION {
+            log!(
+                "Cache",
+                LogCategory::Warn,
+                "Ignoring the match cache made by another version"
+            );
+            return Ok(cache);
+        }
+
+        cache.entries = Mutex::new(file.entries);
+
+        Ok(cache)
+    }
+
+    /// Writes the cache to its file, unless it's disabled
+    pub fn save(&self) -> Result<(), String> {
+        if !self.enabled {
+            return Ok(());
+        }
+
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir).map_err(|err| {
+                format!(
+                    "Failed to create the match cache directory {}: {err}",
+                    dir.display()
+                )
+            })?;
+        }
+
+        let file = CacheFile {
+            version: CACHE_VERSION,
+            entries: self.entries.lock().unwrap().clone(),
+        };
+        let json = serde_json::to_string_pretty(&file)
+            .map_err(|err| format!("Failed to serialize the match cache: {err}"))?;
+
+        // Written aside then renamed, so a kill while writing doesn't truncate the cache
+        let tmp = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp, json)
+            .and_then(|_| std::fs::rename(&tmp, &self.path))
+            .map_err(|err| {
+                format!(
+                    "Failed to write the match cache to {}: {err}",
+                    self.path.display()
+                )
+            })
+    }
+
+    /// The cached match of the track, by Deezer id or ISRC
+    pub fn get(&self, track: &DeezerTrack) -> Option<CacheEntry> {
+        if !self.enabled {
+            return None;
+        }
+
+        let entries = self.entries.lock().unwrap();
+
+        keys(track).iter().find_map(|key| entries.get(key).cloned())
+    }
+
+    /// Remembers the match of the track, under each of its keys
+    pub fn insert(&self, track: &DeezerTrack, found: &SpotifyTrack, confidence: f64) {
+        if !self.enabled {
+            return;
+        }
+
+        let entry = CacheEntry {
+            track: found.clone(),
+            confidence,
+            cached_at: unix_time().as_secs(),
+        };
+        let mut entries = self.entries.lock().unwrap();
+
+        for key in keys(track) {
+            entries.insert(key, entry.clone());
+        }
+    }
+
+    /// Removes the entries older than `max_age` seconds, returns how many were removed
+    pub fn prune(&self, max_age: u64) -> usize {
+        let limit = unix_time().as_secs().saturating_sub(max_age);
+        let mut entries = self.entries.lock().unwrap();
+        let before = entries.len();
+
+        entries.retain(|_, entry| entry.cached_at >= limit);
+
+        before - entries.len()
+    }
+
+    /// Forgets every match
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+
+    /// Prints every cached match, oldest first
+    pub fn inspect(&self) {
+        let entries = self.entries.lock().unwrap();
+        let mut sorted = entries.iter().collect::<Vec<_>>();
+
+        sorted.sort_by_key(|(_, entry)| entry.cached_at);
+
+        for (key, entry) in &sorted {
+            log!(
+                "Cache",
+                LogCategory::Info,
+                "{key} -> \"{}\" by \"{}\" ({}, {:.0}%, {} day(s) old)",
+                entry.track.title,
+                entry.track.artist_name,
+                entry.track.uri(),
+                entry.confidence * 100.0,
+                unix_time().as_secs().saturating_sub(entry.cached_at) / 86400
+            );
+        }
+
+        log!(
+            "Cache",
+            LogCategory::Success,
+            "{} cached match(es) in {}",
+            sorted.len(),
+            self.path.display()
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MatchCache;
+    use crate::deezer::DeezerTrack;
+    use crate::spotify::SpotifyTrack;
+
+    #[test]
+    fn load_ignores_a_truncated_cache() {
+        let dir = std::env::temp_dir().join(format!("dts-cache-{}", std::process::id()));
+        let path = dir.join("matches.json");
+        let track = DeezerTrack {
+            id: 3135556,
+            title: "One More Time".to_owned(),
+            artist_name: "Daft Punk".to_owned(),
+            album_title: None,
+            duration: None,
+            isrc: None,
+            spotify_id: None,
+        };
+        let found = SpotifyTrack {
+            id: "0DiWol3AO6WpXZgp0goxAV".to_owned(),
+            title: "One More Time".to_owned(),
+            artist_name: "Daft Punk".to_owned(),
+        };
+
+        let cache = MatchCache::load(&path).unwrap();
+        cache.insert(&track, &found, 0.9);
+        cache.save().unwrap();
+        assert!(!dir.join("matches.json.tmp").exists());
+        assert_eq!(
+            MatchCache::load(&path)
+                .unwrap()
+                .get(&track)
+                .unwrap()
+                .track
+                .id,
+            found.id
+        );
+
+        let json = std::fs::read_to_string(&path).unwrap();
+        std::fs::write(&path, &json[..json.len() / 2]).unwrap();
+        assert!(MatchCache::load(&path).unwrap().get(&track).is_none());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
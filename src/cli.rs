@@ -0,0 +1,438 @@
+//! Terminal side of the command line: printing, prompts and flags that only make sense there
+
+use std::{
+    fs::File,
+    io::{IsTerminal, Write},
+    path::PathBuf,
+    sync::Mutex,
+};
+
+use clap::{ArgAction, Args, ValueEnum};
+use colored::Colorize;
+use dialoguer::{theme::ColorfulTheme, Confirm, Input, MultiSelect, Select};
+use reqwest::Client;
+
+use deezer_to_spotify::config::Config;
+use deezer_to_spotify::deezer::Deezer;
+use deezer_to_spotify::filter::FilterArgs;
+use deezer_to_spotify::logger::{self, log, LogCategory, Record};
+use deezer_to_spotify::overrides::{choices, parse_track_id, OverrideTarget};
+use deezer_to_spotify::report::ReportEntry;
+use deezer_to_spotify::server::CallbackConfig;
+use deezer_to_spotify::spotify::Spotify;
+use deezer_to_spotify::unix_time;
+
+use crate::bars;
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    Text,
+    /// One JSON object per line, with the provider, playlist and track of the message when known
+    Json,
+}
+
+/// Verbosity and destinations of the messages
+#[derive(Args, Debug, Clone)]
+pub struct LogArgs {
+    /// Show debug messages, twice for traces
+    #[arg(short, long, action = ArgAction::Count, global = true)]
+    pub verbose: u8,
+
+    /// Only show warnings and errors
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// Also write the messages to this file, -q doesn't apply to it
+    #[arg(long, global = true)]
+    pub log_file: Option<PathBuf>,
+
+    /// Format of the messages, JSON disables the progress bars
+    #[arg(long, value_enum, default_value = "text", global = true)]
+    pub log_format: LogFormat,
+}
+
+/// Sends the messages of the library to the terminal and the log file, with the progress bars for
+/// the text format
+pub fn init_logging(args: &LogArgs) -> Result<(), String> {
+    let verbosity = LogCategory::Info.verbosity() + args.verbose;
+    let console = if args.quiet {
+        LogCategory::Warn.verbosity()
+    } else {
+        verbosity
+    };
+    let format = args.log_format;
+    let file = match &args.log_file {
+        Some(path) => Some(Mutex::new(File::create(path).map_err(|err| {
+            format!("Failed to create the log file {}: {err}", path.display())
+        })?)),
+        None => None,
+    };
+
+    if format == LogFormat::Text {
+        bars::init();
+    }
+
+    logger::set_sink(move |record| {
+        if record.category.verbosity() <= console {
+            match format {
+                LogFormat::Text => print_log(record),
+                LogFormat::Json => println!("{}", json_line(record)),
+            }
+        }
+
+        if let Some(file) = &file {
+            if record.category.verbosity() <= verbosity {
+                let line = match format {
+                    LogFormat::Text => text_line(record),
+                    LogFormat::Json => json_line(record),
+                };
+                let _ = writeln!(file.lock().unwrap(), "{line}");
+            }
+        }
+    });
+
+    Ok(())
+}
+
+fn text_line(record: &Record) -> String {
+    format!(
+        "{:.3} {:<7} [{}] {}",
+        unix_time().as_secs_f64(),
+        record.category.name().to_uppercase(),
+        record.title,
+        record.message
+    )
+}
+
+fn json_line(record: &Record) -> String {
+    let mut line = serde_json::json!({
+        "time": unix_time().as_secs_f64(),
+        "level": record.category.name(),
+        "title": record.title,
+        "message": record.message,
+    });
+    let fields = [
+        ("provider", record.fields.provider),
+        ("playlist", record.fields.playlist),
+        ("track", record.fields.track),
+    ];
+
+    for (name, value) in fields {
+        if let Some(value) = value {
+            line[name] = value.into();
+        }
+    }
+
+    line.to_string()
+}
+
+/// Prints the messages of the library, above the progress bars. Warnings and errors go to stderr
+fn print_log(record: &Record) {
+    let title = format!("[{}]", record.title);
+    let title = match record.category {
+        LogCategory::Error => title.red().bold(),
+        LogCategory::Warn => title.yellow(),
+        LogCategory::Info => title.purple(),
+        LogCategory::Success => title.green(),
+        LogCategory::Debug => title.blue(),
+        LogCategory::Trace => title.dimmed(),
+    };
+    let line = format!("{title} {}", record.message);
+
+    match record.category {
+        LogCategory::Error | LogCategory::Warn => bars::eprint(&line),
+        _ => bars::print(&line),
+    }
+}
+
+/// Shows the login links and prompts on stderr, even with -q or the JSON format
+pub fn prompt(line: &str) {
+    bars::eprint(&format!("{} {line}", "[Login]".purple()));
+}
+
+/// Playlist selection flags, added to the `[filters]` section of the config
+#[derive(Args, Debug, Clone)]
+pub struct FilterFlags {
+    /// Only transfer the Deezer playlist with this id (can be repeated)
+    #[arg(long = "include-id", value_name = "ID")]
+    pub include_ids: Vec<i64>,
+
+    /// Never transfer the Deezer playlist with this id (can be repeated)
+    #[arg(long = "exclude-id", value_name = "ID")]
+    pub exclude_ids: Vec<i64>,
+
+    /// Only transfer the playlist with this exact title (can be repeated)
+    #[arg(long = "include", value_name = "TITLE")]
+    pub include_titles: Vec<String>,
+
+    /// Never transfer the playlist with this exact title (can be repeated)
+    #[arg(long = "exclude", value_name = "TITLE")]
+    pub exclude_titles: Vec<String>,
+
+    /// Only transfer playlists whose title matches this pattern, a case insensitive glob
+    /// (e.g. "Road trip*") or a regex when prefixed with "re:" (can be repeated)
+    #[arg(long = "include-pattern", value_name = "PATTERN")]
+    pub include_patterns: Vec<String>,
+
+    /// Never transfer playlists whose title matches this pattern (same syntax as --include-pattern)
+    #[arg(long = "exclude-pattern", value_name = "PATTERN")]
+    pub exclude_patterns: Vec<String>,
+
+    /// Pick the playlists to transfer from a checklist (only when running in a terminal)
+    #[arg(long)]
+    pub pick: bool,
+}
+
+impl FilterFlags {
+    pub fn apply(&self, filters: &mut FilterArgs) {
+        filters.extend(&FilterArgs {
+            include_ids: self.include_ids.clone(),
+            exclude_ids: self.exclude_ids.clone(),
+            include_titles: self.include_titles.clone(),
+            exclude_titles: self.exclude_titles.clone(),
+            include_patterns: self.include_patterns.clone(),
+            exclude_patterns: self.exclude_patterns.clone(),
+            pick: self.pick,
+        });
+    }
+}
+
+/// Command line overrides of the [`CallbackConfig`]
+#[derive(Args, Debug, Clone)]
+pub struct CallbackArgs {
+    /// Host of the callback server. Spotify only accepts loopback IPs (127.0.0.1 or [::1]), not localhost
+    /// [default: 127.0.0.1]
+    #[arg(long, global = true)]
+    pub callback_host: Option<String>,
+
+    /// Port of the callback server, 0 picks a free one. Spotify accepts any port on a loopback IP,
+    /// Deezer needs the redirect URI registered for your app so it needs a fixed port [default: 8080]
+    #[arg(long, global = true)]
+    pub callback_port: Option<u16>,
+
+    /// Path prefix of the callbacks, they're served on <PATH>/Spotify and <PATH>/Deezer
+    #[arg(long, global = true)]
+    pub callback_path: Option<String>,
+
+    /// Don't open the login pages in the browser, only print their links
+    #[arg(long, global = true)]
+    pub no_browser: bool,
+
+    /// Don't start the callback server, paste the URL the browser was redirected to instead
+    /// (for machines the browser can't reach, e.g. over SSH)
+    #[arg(long, global = true)]
+    pub headless: bool,
+}
+
+impl CallbackArgs {
+    pub fn apply(&self, config: &mut CallbackConfig) {
+        if let Some(host) = &self.callback_host {
+            config.host = host.clone();
+        }
+        if let Some(port) = self.callback_port {
+            config.port = port;
+        }
+        if let Some(path) = &self.callback_path {
+            config.path = path.clone();
+        }
+        if self.no_browser {
+            config.open_browser = false;
+        }
+        if self.headless {
+            config.headless = true;
+        }
+    }
+}
+
+/// Reads a secret in the terminal without showing it
+fn ask_secret(prompt: &str) -> Result<String, String> {
+    print!("{prompt}: ");
+    std::io::stdout()
+        .flush()
+        .map_err(|err| format!("Failed to show the prompt: {err}"))?;
+
+    scanpw::try_scanpw(Some('*'))
+        .map(|secret| secret.trim().to_owned())
+        .map_err(|err| format!("Failed to read the {prompt}: {err}"))
+}
+
+fn ask_text(prompt: &str) -> Result<String, String> {
+    Input::<String>::with_theme(&ColorfulTheme::default())
+        .with_prompt(prompt)
+        .interact_text()
+        .map(|text| text.trim().to_owned())
+        .map_err(|err| format!("Failed to read the {prompt}: {err}"))
+}
+
+/// Asks for the missing credentials when running in a terminal, checks them with the token
+/// endpoints and offers to save them in the config file
+pub async fn ask_missing_credentials(
+    config: &mut Config,
+    client: &Client,
+    deezer: bool,
+    spotify: bool,
+) -> Result<(), String> {
+    let missing_deezer = deezer && config.require_deezer().is_err();
+    let missing_spotify = spotify && config.require_spotify().is_err();
+
+    if !missing_deezer && !missing_spotify {
+        return Ok(());
+    }
+
+    if !std::io::stdin().is_terminal() || !std::io::stdout().is_terminal() {
+        if missing_deezer {
+            config.require_deezer()?;
+        }
+        if missing_spotify {
+            config.require_spotify()?;
+        }
+        return Ok(());
+    }
+
+    if missing_deezer {
+        ask_deezer(config, client).await?;
+    }
+    if missing_spotify {
+        ask_spotify(config, client).await?;
+    }
+
+    let Some(path) = config.path.clone() else {
+        return Ok(());
+    };
+
+    let save = Confirm::with_theme(&ColorfulTheme::default())
+        .with_prompt(format!("Save the credentials to {}?", path.display()))
+        .default(true)
+        .interact()
+        .map_err(|err| format!("Failed to read your choice: {err}"))?;
+
+    if save {
+        config.save_credentials(&path, missing_deezer, missing_spotify)?;
+        log!(
+            "Config",
+            LogCategory::Success,
+            "Saved the credentials to {}",
+            path.display()
+        );
+    }
+
+    Ok(())
+}
+
+/// Asks until Deezer accepts the credentials
+async fn ask_deezer(config: &mut Config, client: &Client) -> Result<(), String> {
+    loop {
+        config.deezer.app_id = ask_text("Deezer app id")?;
+        config.deezer.secret_key = ask_secret("Deezer secret key")?;
+
+        if Deezer::new(client, config.deezer.clone())
+            .check_credentials()
+            .await?
+        {
+            return Ok(());
+        }
+    }
+}
+
+/// Asks until Spotify accepts the credentials
+async fn ask_spotify(config: &mut Config, client: &Client) -> Result<(), String> {
+    loop {
+        config.spotify.client_id = ask_text("Spotify client id")?;
+        config.spotify.client_secret = Some(ask_secret(
+            "Spotify client secret (leave empty to log in with PKCE)",
+        )?)
+        .filter(|secret| !secret.is_empty());
+
+        if Spotify::new(client, config.spotify.clone())
+            .check_credentials()
+            .await?
+        {
+            return Ok(());
+        }
+    }
+}
+
+/// Lets the user uncheck playlists from the ones matching the filters, returns the ones to keep
+pub fn pick_playlists<T>(playlists: Vec<T>, title: impl Fn(&T) -> &str) -> Result<Vec<T>, String> {
+    if playlists.is_empty() {
+        return Ok(playlists);
+    }
+
+    if !std::io::stdin().is_terminal() || !std::io::stderr().is_terminal() {
+        log!(
+            "Deezer",
+            LogCategory::Info,
+            "Not running in a terminal, skipping the playlist picker"
+        );
+        return Ok(playlists);
+    }
+
+    let titles = playlists.iter().map(title).collect::<Vec<_>>();
+    let picked = MultiSelect::with_theme(&ColorfulTheme::default())
+        .with_prompt("Select the playlists to transfer (space to toggle, enter to confirm)")
+        .items(&titles)
+        .defaults(&vec![true; titles.len()])
+        .interact()
+        .map_err(|err| format!("Failed to pick the playlists to transfer: {err}"))?;
+
+    Ok(playlists
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| picked.contains(i))
+        .map(|(_, playlist)| playlist)
+        .collect())
+}
+
+/// Asks the user what to do with the track of the report
+pub fn ask_override(entry: &ReportEntry) -> Result<Option<OverrideTarget>, String> {
+    let theme = ColorfulTheme::default();
+    let choices = choices(entry);
+    let mut items = choices
+        .iter()
+        .map(|(track, score)| {
+            format!(
+                "{} - {} ({:.0}%)",
+                track.title,
+                track.artist_name,
+                score * 100.0
+            )
+        })
+        .collect::<Vec<_>>();
+    let candidates = items.len();
+
+    items.push(String::from("Enter a Spotify track link or URI"));
+    items.push(String::from("Always skip this track"));
+    items.push(String::from("Leave it for later"));
+
+    let choice = Select::with_theme(&theme)
+        .with_prompt(format!(
+            "[{}] \"{}\" by \"{}\"",
+            entry.playlist, entry.title, entry.artist_name
+        ))
+        .items(&items)
+        .default(0)
+        .interact()
+        .map_err(|err| format!("Failed to read your choice: {err}"))?;
+
+    if choice < candidates {
+        return Ok(Some(OverrideTarget::Track(choices[choice].0.id.clone())));
+    }
+
+    Ok(match choice - candidates {
+        0 => {
+            let id: String = Input::with_theme(&theme)
+                .with_prompt("Spotify track")
+                .validate_with(|input: &String| {
+                    parse_track_id(input)
+                        .map(|_| ())
+                        .ok_or("Not a Spotify track link or URI")
+                })
+                .interact_text()
+                .map_err(|err| format!("Failed to read the Spotify track: {err}"))?;
+
+            parse_track_id(&id).map(OverrideTarget::Track)
+        }
+        1 => Some(OverrideTarget::Skip),
+        _ => None,
+    })
+}
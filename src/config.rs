@@ -0,0 +1,346 @@
+use std::{
+    fmt::Display,
+    io::Write,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use serde::Deserialize;
+
+use crate::filter::FilterArgs;
+use crate::matcher::Thresholds;
+use crate::server::CallbackConfig;
+
+/// Credentials of the Deezer app, from <https://developers.deezer.com/myapps>
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct DeezerCredentials {
+    /// Application id
+    pub app_id: String,
+    /// Secret key of the application
+    pub secret_key: String,
+}
+
+/// Credentials of the Spotify app, from <https://developer.spotify.com/dashboard>
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct SpotifyCredentials {
+    /// Client id of the application
+    pub client_id: String,
+    /// Optional, without it the PKCE flow is used
+    pub client_secret: Option<String>,
+}
+
+/// How the Spotify playlists are named, and what happens to the ones that already have the name
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Naming {
+    /// Name of the Spotify playlists, `{title}` is replaced by the title of the Deezer playlist
+    pub playlist: String,
+    /// Replace the tracks of the Spotify playlist that already has the name, removing the ones that
+    /// aren't on Deezer, instead of creating another playlist
+    pub replace_existing: bool,
+}
+
+impl Default for Naming {
+    fn default() -> Self {
+        Self {
+            playlist: String::from("{title}"),
+            replace_existing: false,
+        }
+    }
+}
+
+impl Naming {
+    /// The name of the Spotify playlist of the Deezer playlist `title`
+    pub fn playlist_name(&self, title: &str) -> String {
+        self.playlist.replace("{title}", title)
+    }
+}
+
+/// How much work is done at the same time
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Concurrency {
+    /// How many tracks are searched on Spotify at the same time
+    pub searches: usize,
+}
+
+impl Default for Concurrency {
+    fn default() -> Self {
+        Self { searches: 4 }
+    }
+}
+
+/// Settings of the config file, every section is optional.
+///
+/// Values are taken from the file, then the environment variables, then the command line flags.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// `[deezer]` section
+    pub deezer: DeezerCredentials,
+    /// `[spotify]` section
+    pub spotify: SpotifyCredentials,
+    /// `[callback]` section
+    pub callback: CallbackConfig,
+    /// `[matching]` section
+    pub matching: Thresholds,
+    /// `[filters]` section
+    pub filters: FilterArgs,
+    /// `[naming]` section
+    pub naming: Naming,
+    /// `[concurrency]` section
+    pub concurrency: Concurrency,
+    /// The file the config was loaded from, or where it would be, credentials typed in are saved there
+    #[serde(skip)]
+    pub path: Option<PathBuf>,
+}
+
+/// Writes the file so only the current user can read it, creating its directory if needed
+pub fn write_private(path: &Path, content: &str) -> Result<(), String> {
+    if let Some(dir) = path.parent() {
+        let mut builder = std::fs::DirBuilder::new();
+        builder.recursive(true);
+        #[cfg(unix)]
+        std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+
+        builder
+            .create(dir)
+            .map_err(|err| format!("Failed to create the directory {}: {err}", dir.display()))?;
+    }
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    let mut file = options
+        .open(path)
+        .map_err(|err| format!("Failed to open {}: {err}", path.display()))?;
+
+    // The mode above only applies to new files
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        file.set_permissions(std::fs::Permissions::from_mode(0o600))
+            .map_err(|err| {
+                format!(
+                    "Failed to restrict the permissions of {}: {err}",
+                    path.display()
+                )
+            })?;
+    }
+
+    file.write_all(content.as_bytes())
+        .map_err(|err| format!("Failed to write {}: {err}", path.display()))
+}
+
+/// The value of the environment variable, `None` if it's unset or empty
+fn env_var<T>(name: &str) -> Result<Option<T>, String>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    match std::env::var(name) {
+        Ok(value) if !value.is_empty() => value
+            .parse()
+            .map(Some)
+            .map_err(|err| format!("Invalid value \"{value}\" for {name}: {err}")),
+        _ => Ok(None),
+    }
+}
+
+impl Config {
+    /// `~/.config/deezer_to_spotify/config.toml` on Linux, the platform equivalent elsewhere
+    pub fn default_path() -> Option<PathBuf> {
+        Some(
+            dirs::config_dir()?
+                .join("deezer_to_spotify")
+                .join("config.toml"),
+        )
+    }
+
+    /// Loads the config file and applies the environment variables on top of it.
+    ///
+    /// Without `path`, the file in the user config directory is used if it exists.
+    pub fn load(path: Option<&Path>) -> Result<Self, String> {
+        let path = match path {
+            Some(path) if !path.exists() => {
+                return Err(format!("The config file {} doesn't exist", path.display()))
+            }
+            Some(path) => Some(path.to_owned()),
+            None => Self::default_path(),
+        };
+
+        let mut config = match path.as_ref().filter(|path| path.exists()) {
+            Some(path) => {
+                let content = std::fs::read_to_string(path).map_err(|err| {
+                    format!("Failed to read the config file {}: {err}", path.display())
+                })?;
+
+                toml::from_str(&content).map_err(|err| {
+                    format!("Failed to parse the config file {}: {err}", path.display())
+                })?
+            }
+            None => Self::default(),
+        };
+
+        config.path = path;
+        config.apply_env()?;
+
+        Ok(config)
+    }
+
+    fn apply_env(&mut self) -> Result<(), String> {
+        if let Some(id) = env_var("DEEZER_APP_ID")? {
+            self.deezer.app_id = id;
+        }
+        if let Some(secret) = env_var("DEEZER_SECRET_KEY")? {
+            self.deezer.secret_key = secret;
+        }
+        if let Some(id) = env_var("SPOTIFY_CLIENT_ID")? {
+            self.spotify.client_id = id;
+        }
+        if let Some(secret) = env_var("SPOTIFY_CLIENT_SECRET")? {
+            self.spotify.client_secret = Some(secret);
+        }
+        if let Some(host) = env_var("DTS_CALLBACK_HOST")? {
+            self.callback.host = host;
+        }
+        if let Some(port) = env_var("DTS_CALLBACK_PORT")? {
+            self.callback.port = port;
+        }
+        if let Some(path) = env_var("DTS_CALLBACK_PATH")? {
+            self.callback.path = path;
+        }
+        if let Some(accept) = env_var("DTS_MATCH_ACCEPT")? {
+            self.matching.accept = accept;
+        }
+        if let Some(confident) = env_var("DTS_MATCH_CONFIDENT")? {
+            self.matching.confident = confident;
+        }
+        if let Some(playlist) = env_var("DTS_PLAYLIST_NAME")? {
+            self.naming.playlist = playlist;
+        }
+        if let Some(replace) = env_var("DTS_REPLACE_EXISTING")? {
+            self.naming.replace_existing = replace;
+        }
+        if let Some(searches) = env_var("DTS_CONCURRENCY")? {
+            self.concurrency.searches = searches;
+        }
+
+        // An empty secret means the PKCE flow, like a missing one
+        self.spotify.client_secret = self
+            .spotify
+            .client_secret
+            .take()
+            .filter(|secret| !secret.is_empty());
+
+        Ok(())
+    }
+
+    /// Checks everything but the credentials, which are only needed by some commands
+    pub fn validate(&self) -> Result<(), String> {
+        let Thresholds { accept, confident } = self.matching;
+
+        if !(0.0..=1.0).contains(&accept) || !(0.0..=1.0).contains(&confident) {
+            return Err(String::from(
+                "The matching thresholds have to be between 0 and 1",
+            ));
+        }
+        if accept > confident {
+            return Err(String::from(
+                "The accept matching threshold can't be higher than the confident one",
+            ));
+        }
+        if self.callback.host.is_empty() {
+            return Err(String::from("The callback host can't be empty"));
+        }
+        if !self.naming.playlist.contains("{title}") {
+            return Err(String::from(
+                "The playlist name has to contain {title}, otherwise every playlist would get the same name",
+            ));
+        }
+        if self.concurrency.searches == 0 {
+            return Err(String::from("The concurrency has to be at least 1"));
+        }
+
+        Ok(())
+    }
+
+    /// Fails if the Deezer app id or secret key is missing
+    pub fn require_deezer(&self) -> Result<(), String> {
+        if self.deezer.app_id.is_empty() || self.deezer.secret_key.is_empty() {
+            return Err(String::from(
+                "Missing Deezer credentials, set app_id and secret_key in the [deezer] section of the config \
+                 or the DEEZER_APP_ID and DEEZER_SECRET_KEY environment variables",
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Fails if the Spotify client id is missing, the secret is optional
+    pub fn require_spotify(&self) -> Result<(), String> {
+        if self.spotify.client_id.is_empty() {
+            return Err(String::from(
+                "Missing Spotify credentials, set client_id in the [spotify] section of the config \
+                 or the SPOTIFY_CLIENT_ID environment variable",
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Writes the credentials in the config file, keeping its other settings
+    pub fn save_credentials(&self, path: &Path, deezer: bool, spotify: bool) -> Result<(), String> {
+        let mut table = if path.exists() {
+            std::fs::read_to_string(path)
+                .map_err(|err| format!("Failed to read the config file {}: {err}", path.display()))?
+                .parse::<toml::Table>()
+                .map_err(|err| {
+                    format!("Failed to parse the config file {}: {err}", path.display())
+                })?
+        } else {
+            toml::Table::new()
+        };
+
+        let mut section = |name: &str, values: Vec<(&str, &str)>| {
+            let section = table
+                .entry(name)
+                .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+
+            if let toml::Value::Table(section) = section {
+                for (key, value) in values {
+                    section.insert(key.to_owned(), toml::Value::String(value.to_owned()));
+                }
+            }
+        };
+
+        if deezer {
+            section(
+                "deezer",
+                vec![
+                    ("app_id", &self.deezer.app_id),
+                    ("secret_key", &self.deezer.secret_key),
+                ],
+            );
+        }
+        if spotify {
+            let mut values = vec![("client_id", self.spotify.client_id.as_str())];
+
+            if let Some(secret) = &self.spotify.client_secret {
+                values.push(("client_secret", secret));
+            }
+
+            section("spotify", values);
+        }
+
+        let content = toml::to_string_pretty(&table)
+            .map_err(|err| format!("Failed to serialize the config: {err}"))?;
+
+        write_private(path, &content)
+    }
+}
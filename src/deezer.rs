@@ -1,45 +1,79 @@
-use std::{
-    sync::{Arc, OnceLock},
-    time::Duration,
-};
+use std::{collections::HashMap, sync::Mutex, time::Duration};
 
-use loading::Loading;
+use futures_util::{stream, StreamExt};
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
-use serde_json::json;
-use tokio::{sync::RwLock, time::sleep};
 
-use crate::logger::{log, LogCategory};
+use crate::config::DeezerCredentials;
+use crate::filter::PlaylistFilter;
+use crate::json::{array_at, i64_at, str_at};
+use crate::logger::{self, log, LogCategory};
+use crate::server::Server;
+use crate::tokens::{self, SavedToken};
+use crate::unix_time;
 
 const TOKEN_URL: &str = "https://connect.deezer.com/oauth/access_token.php";
-const REDIRECT_URI: &str = "http://localhost:8080/Deezer";
-const PERMS: [&str; 2] = ["basic_access", "manage_library"];
-
-pub static CODE: OnceLock<Arc<RwLock<String>>> = OnceLock::new();
-
+const PERMS: [&str; 3] = ["basic_access", "manage_library", "offline_access"];
+const TOKEN_KEY: &str = "deezer";
+/// Tracks whose ISRC is fetched at the same time
+const ISRC_FETCHES: usize = 4;
+/// Deezer allows 50 requests every 5 seconds, over quota requests are sent again after this
+const QUOTA_WAIT: Duration = Duration::from_secs(5);
+const QUOTA_RETRIES: u32 = 3;
+
+/// Deezer API client, log in with [`crate::App::init`] before using it
 #[derive(Debug)]
 pub struct Deezer<'app> {
-    pub email: String,
-    pub password: String,
     client: &'app Client,
+    credentials: DeezerCredentials,
     access_token: String,
+    redirect_uri: String,
+    /// ISRC of the tracks by id, `None` if Deezer doesn't know it
+    isrcs: Mutex<HashMap<i64, Option<String>>>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct DeezerPlaylistResponse {
-    data: Vec<DeezerPlaylist>,
+/// A playlist of the logged in user, as listed by Deezer (without its tracks)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DeezerPlaylistInfo {
+    /// Deezer id of the playlist
+    pub id: i64,
+    /// Title of the playlist
+    pub title: String,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// A Deezer playlist with its tracks, also read from the imported files
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct DeezerPlaylist {
+    /// Deezer id of the playlist, 0 when it doesn't come from Deezer
+    pub id: i64,
+    /// Title of the playlist
     pub title: String,
+    /// Tracks of the playlist, in order
     pub tracks: Vec<DeezerTrack>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// A track of a Deezer playlist, the fields of the other sources are mapped to it
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct DeezerTrack {
+    /// Deezer id of the track, 0 when it doesn't come from Deezer
+    #[serde(default)]
+    pub id: i64,
+    /// Title of the track
     pub title: String,
+    /// Name of the main artist
     pub artist_name: String,
+    /// Title of the album
+    #[serde(default)]
+    pub album_title: Option<String>,
+    /// In seconds
+    #[serde(default)]
+    pub duration: Option<u64>,
+    /// International Standard Recording Code, the most reliable way to find the track on Spotify
+    #[serde(default)]
+    pub isrc: Option<String>,
+    /// Spotify track already known for it, e.g. from the CSV of another tool, used without searching
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spotify_id: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -48,52 +82,79 @@ struct DeezerUser {
     name: String,
 }
 
+/// Reads the answer of the token endpoint to a dummy code: true if only the code is refused, false
+/// if the app id or the secret is, `None` if it's neither.
+///
+/// The error is plain text (`wrong code`) or a JSON error object, whose `code` field isn't about
+/// the OAuth code, so only its message is looked at
+fn credentials_check(body: &str) -> Option<bool> {
+    let message = match serde_json::from_str::<serde_json::Value>(body) {
+        Ok(json) => {
+            let error = json.get("error")?;
+            error
+                .get("message")
+                .and_then(serde_json::Value::as_str)
+                .or(error.as_str())?
+                .to_owned()
+        }
+        Err(_) => body.to_owned(),
+    }
+    .to_lowercase();
+
+    if ["app_id", "app id", "appid", "application", "secret"]
+        .iter()
+        .any(|word| message.contains(word))
+    {
+        Some(false)
+    } else if message.contains("code") {
+        Some(true)
+    } else {
+        None
+    }
+}
+
 #[async_trait::async_trait]
 impl<'app> crate::App for Deezer<'app> {
     type Error = String;
 
-    async fn init(&mut self) {
-        println!("{}", Deezer::get_auth_url());
+    async fn init(&mut self, server: &Server) -> Result<(), Self::Error> {
+        if self.restore_session().await {
+            log!("Deezer", LogCategory::Success, "Logged in to Deezer!");
+            return Ok(());
+        }
 
-        let deez_load = Loading::default();
-        deez_load.text(String::from("Please sign in to Deezer with the link above"));
+        if server.is_ephemeral() {
+            log!(
+                "Deezer",
+                LogCategory::Warn,
+                "Deezer only redirects to the URI registered for your app, a random callback port will most likely be refused"
+            );
+        }
 
-        let mut timeout = 0;
-        while CODE.get().is_none() {
-            if timeout == 150 {
-                deez_load.fail(String::from("[5min timeout] Failed to login to Deezer"));
-                std::process::exit(1);
-            }
+        let login = server.login("Deezer").await?;
+        self.redirect_uri = login.redirect_uri.clone();
 
-            timeout += 1;
-            sleep(Duration::from_secs(2)).await;
-        }
+        server.show_auth_url(&self.get_auth_url(&login.state));
 
-        match self.fetch_token().await {
-            Ok(_) => deez_load.success(String::from("Logged in to Deezer!")),
-            Err(err) => {
-                deez_load.fail(format!("Failed to login Deezer! ({err})"));
-                std::process::exit(1);
-            }
-        }
+        let code = login.wait().await?;
 
-        deez_load.end();
-    }
+        log!("Deezer", LogCategory::Info, "Logging in to Deezer...");
+
+        self.fetch_token(&code)
+            .await
+            .map_err(|err| format!("Failed to login Deezer! ({err})"))?;
+
+        log!("Deezer", LogCategory::Success, "Logged in to Deezer!");
 
-    async fn fetch_token(&mut self) -> Result<(), Self::Error> {
-        let id = dotenv::var("DEEZER_APP_ID")
-            .map_err(|err| format!("Failed to get Deezer app ID from env: {err}"))?;
-        let secret = dotenv::var("DEEZER_SECRET_KEY")
-            .map_err(|err| format!("Failed to get Deezer client SECRET from env: {err}"))?;
+        Ok(())
+    }
 
+    async fn fetch_token(&mut self, code: &str) -> Result<(), Self::Error> {
         let res = self
             .client
             .post(format!(
                 "{}?app_id={}&secret={}&code={}&output=json",
-                TOKEN_URL,
-                id,
-                secret,
-                CODE.get().unwrap().read().await
+                TOKEN_URL, self.credentials.app_id, self.credentials.secret_key, code
             ))
             .header("Content-Type", "application/x-www-form-urlencoded")
             .header("Content-Length", "0")
@@ -118,30 +179,108 @@ impl<'app> crate::App for Deezer<'app> {
             .as_str()
             .ok_or_else(|| format!("Failed to get Deezer access token from json result: {body}"))?
             .to_owned();
+        logger::add_secret(&self.access_token);
+
+        // With the offline_access permission, the token never expires and `expires` is 0
+        let expires_at = body["expires"]
+            .as_u64()
+            .filter(|&expires| expires > 0)
+            .map(|expires| unix_time().as_secs() + expires);
+
+        if let Err(err) = tokens::save(
+            TOKEN_KEY,
+            SavedToken {
+                access_token: self.access_token.clone(),
+                refresh_token: None,
+                expires_at,
+            },
+        ) {
+            log!(
+                "Deezer",
+                LogCategory::Warn,
+                "Couldn't save the Deezer token: {err}"
+            );
+        }
 
         Ok(())
     }
 
-    fn get_auth_url() -> String {
-        let id = dotenv::var("DEEZER_APP_ID")
-            .map_err(|err| format!("Failed to get Deezer app ID from env {err}"))
-            .unwrap();
+    async fn restore_session(&mut self) -> bool {
+        let Some(saved) = tokens::load(TOKEN_KEY) else {
+            return false;
+        };
+
+        if saved.is_expired() {
+            return false;
+        }
+
+        self.access_token = saved.access_token;
+        logger::add_secret(&self.access_token);
+
+        self.get_me().await.is_ok()
+    }
+
+    fn get_auth_url(&self, state: &str) -> String {
         let perms = PERMS.join(",");
 
         format!(
-            "https://connect.deezer.com/oauth/auth.php?app_id={}&redirect_uri={}&perms={}",
-            id, REDIRECT_URI, perms
+            "https://connect.deezer.com/oauth/auth.php?app_id={}&redirect_uri={}&perms={}&state={}",
+            self.credentials.app_id,
+            form_urlencoded::byte_serialize(self.redirect_uri.as_bytes()).collect::<String>(),
+            perms,
+            state
         )
     }
 }
 
 impl<'app> Deezer<'app> {
-    pub fn new(client: &'app Client) -> Self {
+    /// A client that isn't logged in yet
+    pub fn new(client: &'app Client, credentials: DeezerCredentials) -> Self {
         Self {
-            email: String::new(),
-            password: String::new(),
             client,
+            credentials,
             access_token: String::new(),
+            redirect_uri: String::new(),
+            isrcs: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Checks the app id and secret with a dummy code, Deezer complains about the code only if they're right.
+    /// Returns false if they're refused
+    pub async fn check_credentials(&self) -> Result<bool, <Deezer<'app> as crate::App>::Error> {
+        let res = self
+            .client
+            .post(TOKEN_URL)
+            .query(&[
+                ("app_id", self.credentials.app_id.as_str()),
+                ("secret", self.credentials.secret_key.as_str()),
+                ("code", "check"),
+                ("output", "json"),
+            ])
+            .header("Content-Length", "0")
+            .send()
+            .await
+            .map_err(|err| format!("Failed to send Deezer token request: {err}"))?;
+
+        let body = res
+            .text()
+            .await
+            .map_err(|err| format!("Failed to read Deezer token response: {err}"))?;
+
+        match credentials_check(&body) {
+            Some(accepted) => {
+                if !accepted {
+                    log!(
+                        "Deezer",
+                        LogCategory::Warn,
+                        "Deezer refused the app id or secret key: {body}"
+                    );
+                }
+                Ok(accepted)
+            }
+            None => Err(format!(
+                "Unexpected Deezer response while checking the credentials: {body}"
+            )),
         }
     }
 
@@ -169,115 +308,288 @@ impl<'app> Deezer<'app> {
             .await
             .map_err(|err| format!("Failed to get Deezer me json result: {err}"))?;
 
+        // Deezer answers invalid tokens with a 200 and an error object
+        if let Some(err) = body.get("error") {
+            return Err(format!("Failed to fetch Deezer me: {err}"));
+        }
+
         Ok(DeezerUser {
-            id: body["id"].as_i64().unwrap(),
-            name: body["name"].as_str().unwrap().to_owned(),
+            id: i64_at(&body, "/id", "Deezer me")?,
+            name: str_at(&body, "/name", "Deezer me")?,
         })
     }
 
-    async fn get_playlist_tracks(
+    /// Every track of the playlist, in order. Deezer only lists their ISRC when it's known already, see
+    /// [`Deezer::get_isrc`]
+    pub async fn get_playlist_tracks(
         &self,
         id: i64,
     ) -> Result<Vec<DeezerTrack>, <Deezer<'app> as crate::App>::Error> {
-        let res = self
-            .client
-            .get(format!(
-                "https://api.deezer.com/playlist/{}/tracks?output=json&access_token={}",
-                id, self.access_token
-            ))
-            .send()
-            .await
-            .map_err(|err| format!("Failed to send Deezer playlist tracks request: {err}"))?;
+        let mut next = Some(format!(
+            "https://api.deezer.com/playlist/{}/tracks?output=json&limit=100&access_token={}",
+            id, self.access_token
+        ));
+        let mut v = Vec::new();
 
-        if !res.status().is_success() {
-            return Err(format!(
-                "Failed to fetch Deezer playlist tracks: ({}) {:?}",
-                res.status(),
-                res.text().await
-            ));
-        }
+        while let Some(url) = next {
+            log!("Deezer", LogCategory::Trace, "GET {url}");
 
-        let body: serde_json::Value = res
-            .json()
-            .await
-            .map_err(|err| format!("Failed to get Deezer playlist tracks json result: {err}"))?;
+            let res =
+                self.client.get(url).send().await.map_err(|err| {
+                    format!("Failed to send Deezer playlist tracks request: {err}")
+                })?;
 
-        let mut v = Vec::new();
+            if !res.status().is_success() {
+                return Err(format!(
+                    "Failed to fetch Deezer playlist tracks: ({}) {:?}",
+                    res.status(),
+                    res.text().await
+                ));
+            }
 
-        for track in body.get("data").unwrap_or(&json!([])).as_array().unwrap() {
-            let title = track["title"].as_str().unwrap().to_owned();
-            let artist_name = track["artist"]["name"].as_str().unwrap().to_owned();
+            let body: serde_json::Value = res.json().await.map_err(|err| {
+                format!("Failed to get Deezer playlist tracks json result: {err}")
+            })?;
 
-            log!(
-                "Deezer",
-                LogCategory::Info,
-                "| Found track \"{title}\" by \"{artist_name}\""
-            );
+            if let Some(err) = body.get("error") {
+                return Err(format!("Failed to fetch Deezer playlist tracks: {err}"));
+            }
 
-            v.push(DeezerTrack { title, artist_name })
+            for track in array_at(&body, "/data", "Deezer playlist tracks")? {
+                let title = str_at(track, "/title", "Deezer playlist tracks")?;
+                let artist_name = str_at(track, "/artist/name", "Deezer playlist tracks")?;
+
+                log!(
+                    "Deezer",
+                    LogCategory::Debug,
+                    { track: title },
+                    "| Found track \"{title}\" by \"{artist_name}\""
+                );
+
+                v.push(DeezerTrack {
+                    id: track["id"].as_i64().unwrap_or_default(),
+                    title,
+                    artist_name,
+                    album_title: track["album"]["title"].as_str().map(str::to_owned),
+                    duration: track["duration"].as_u64(),
+                    isrc: track["isrc"].as_str().map(str::to_owned),
+                    spotify_id: None,
+                })
+            }
+
+            next = self.next_page(&body);
         }
 
         Ok(v)
     }
 
-    pub async fn get_playlists(
-        &self,
-    ) -> Result<Vec<DeezerPlaylist>, <Deezer<'app> as crate::App>::Error> {
-        let res = self
-            .client
-            .get(format!(
-                "https://api.deezer.com/user/me/playlists?output=json&access_token={}",
-                self.access_token
-            ))
-            .send()
-            .await
-            .map_err(|err| format!("Failed to send Deezer playlists request: {err}"))?;
+    /// Fetches the missing ISRCs of the tracks of the playlist, e.g. before exporting it
+    pub async fn fill_isrcs(&self, playlist: &mut DeezerPlaylist) {
+        let isrcs = stream::iter(&playlist.tracks)
+            .map(|track| async move {
+                match &track.isrc {
+                    Some(isrc) => Some(isrc.clone()),
+                    None => self.get_isrc(track.id).await,
+                }
+            })
+            .buffered(ISRC_FETCHES)
+            .collect::<Vec<_>>()
+            .await;
 
-        if !res.status().is_success() {
-            return Err(format!(
-                "Failed to fetch Deezer playlists: ({}) {:?}",
-                res.status(),
-                res.text().await
-            ));
+        for (track, isrc) in playlist.tracks.iter_mut().zip(isrcs) {
+            track.isrc = isrc;
         }
+    }
 
-        let body: serde_json::Value = res
-            .json()
-            .await
-            .map_err(|err| format!("Failed to get Deezer playlists json result: {err}"))?;
+    /// The URL of the next page of a list, with the access token that Deezer may leave out
+    fn next_page(&self, body: &serde_json::Value) -> Option<String> {
+        let next = body["next"].as_str()?;
 
-        let owner = self.get_me().await.unwrap();
+        if next.contains("access_token=") {
+            Some(next.to_owned())
+        } else {
+            Some(format!("{next}&access_token={}", self.access_token))
+        }
+    }
 
-        let mut v = Vec::new();
+    /// The ISRC of the track, fetched once per track since the playlists don't list it. `None` if
+    /// Deezer doesn't know it or it couldn't be fetched, the track is then matched without it
+    pub async fn get_isrc(&self, id: i64) -> Option<String> {
+        if id == 0 {
+            return None;
+        }
 
-        for playlist in body.get("data").unwrap_or(&json!([])).as_array().unwrap() {
-            if playlist["type"].as_str().unwrap() != "playlist"
-                || playlist["is_loved_track"].as_bool().unwrap()
-                || playlist["creator"]["id"].as_i64().unwrap() != owner.id
-            {
-                continue;
+        if let Some(isrc) = self.isrcs.lock().unwrap().get(&id) {
+            return isrc.clone();
+        }
+
+        match self.fetch_isrc(id).await {
+            Ok(isrc) => {
+                self.isrcs.lock().unwrap().insert(id, isrc.clone());
+                isrc
+            }
+            Err(err) => {
+                log!(
+                    "Deezer",
+                    LogCategory::Debug,
+                    "Couldn't get the ISRC of the track {id}: {err}"
+                );
+                None
+            }
+        }
+    }
+
+    async fn fetch_isrc(&self, id: i64) -> Result<Option<String>, String> {
+        let mut retries = 0;
+
+        loop {
+            let url = format!(
+                "https://api.deezer.com/track/{id}?output=json&access_token={}",
+                self.access_token
+            );
+            log!("Deezer", LogCategory::Trace, "GET {url}");
+
+            let body: serde_json::Value = self
+                .client
+                .get(url)
+                .send()
+                .await
+                .map_err(|err| format!("Failed to send Deezer track request: {err}"))?
+                .json()
+                .await
+                .map_err(|err| format!("Failed to get Deezer track json result: {err}"))?;
+
+            match body.get("error") {
+                // Quota exceeded
+                Some(err) if err["code"].as_i64() == Some(4) && retries < QUOTA_RETRIES => {
+                    retries += 1;
+                    tokio::time::sleep(QUOTA_WAIT).await;
+                }
+                Some(err) => return Err(err.to_string()),
+                None => {
+                    return Ok(body["isrc"]
+                        .as_str()
+                        .filter(|isrc| !isrc.is_empty())
+                        .map(str::to_owned))
+                }
             }
+        }
+    }
 
-            let title = playlist["title"].as_str().unwrap().to_owned();
+    /// The playlists created by the user that pass the filter, without the loved tracks
+    pub async fn list_playlists(
+        &self,
+        filter: &PlaylistFilter,
+    ) -> Result<Vec<DeezerPlaylistInfo>, <Deezer<'app> as crate::App>::Error> {
+        let owner = self.get_me().await?;
+        let mut next = Some(format!(
+            "https://api.deezer.com/user/me/playlists?output=json&limit=100&access_token={}",
+            self.access_token
+        ));
+        let mut candidates = Vec::new();
+
+        while let Some(url) = next {
+            let res = self
+                .client
+                .get(url)
+                .send()
+                .await
+                .map_err(|err| format!("Failed to send Deezer playlists request: {err}"))?;
+
+            if !res.status().is_success() {
+                return Err(format!(
+                    "Failed to fetch Deezer playlists: ({}) {:?}",
+                    res.status(),
+                    res.text().await
+                ));
+            }
 
-            log!("Deezer", LogCategory::Info, "Found playlist \"{title}\"");
+            let body: serde_json::Value = res
+                .json()
+                .await
+                .map_err(|err| format!("Failed to get Deezer playlists json result: {err}"))?;
 
-            v.push(DeezerPlaylist {
-                title,
-                tracks: self
-                    .get_playlist_tracks(playlist["id"].as_i64().unwrap().to_owned())
-                    .await
-                    .unwrap(),
-            })
+            if let Some(err) = body.get("error") {
+                return Err(format!("Failed to fetch Deezer playlists: {err}"));
+            }
+
+            for playlist in array_at(&body, "/data", "Deezer playlists")? {
+                if playlist["type"].as_str() != Some("playlist")
+                    || playlist["is_loved_track"].as_bool().unwrap_or_default()
+                    || playlist["creator"]["id"].as_i64() != Some(owner.id)
+                {
+                    continue;
+                }
+
+                let id = i64_at(playlist, "/id", "Deezer playlists")?;
+                let title = str_at(playlist, "/title", "Deezer playlists")?;
+
+                if !filter.matches(id, &title) {
+                    log!(
+                        "Deezer",
+                        LogCategory::Debug,
+                        { playlist: title },
+                        "Skipping playlist \"{title}\" (filtered out)"
+                    );
+                    continue;
+                }
+
+                candidates.push(DeezerPlaylistInfo { id, title });
+            }
+
+            next = self.next_page(&body);
         }
 
+        Ok(candidates)
+    }
+
+    /// Fetches the tracks of the playlist
+    pub async fn get_playlist(
+        &self,
+        playlist: DeezerPlaylistInfo,
+    ) -> Result<DeezerPlaylist, <Deezer<'app> as crate::App>::Error> {
+        let DeezerPlaylistInfo { id, title } = playlist;
+
         log!(
             "Deezer",
-            LogCategory::Success,
-            "Found {} Deezer playlist(s)",
-            v.len()
+            LogCategory::Info,
+            { playlist: title },
+            "Found playlist \"{title}\""
         );
 
-        Ok(v)
+        Ok(DeezerPlaylist {
+            id,
+            title,
+            tracks: self.get_playlist_tracks(id).await?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::credentials_check;
+
+    #[test]
+    fn credentials_check_tells_the_code_from_the_credentials() {
+        assert_eq!(credentials_check("wrong code"), Some(true));
+        assert_eq!(
+            credentials_check(
+                r#"{"error":{"type":"OAuthException","message":"Invalid code","code":4}}"#
+            ),
+            Some(true)
+        );
+        assert_eq!(credentials_check("wrong app_id"), Some(false));
+        assert_eq!(
+            credentials_check(
+                r#"{"error":{"type":"OAuthException","message":"Wrong secret","code":4}}"#
+            ),
+            Some(false)
+        );
+        // The `code` field of the error isn't about the OAuth code
+        assert_eq!(
+            credentials_check(r#"{"error":{"type":"Exception","message":"Quota","code":4}}"#),
+            None
+        );
+        assert_eq!(credentials_check(r#"{"access_token":"abc"}"#), None);
     }
 }
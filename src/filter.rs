@@ -0,0 +1,193 @@
+use globset::{GlobBuilder, GlobMatcher};
+use regex::Regex;
+use serde::Deserialize;
+
+/// Playlist selection rules, from the `[filters]` section of the config and the command line
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct FilterArgs {
+    /// Only the Deezer playlists with these ids are transferred
+    pub include_ids: Vec<i64>,
+    /// The Deezer playlists with these ids are never transferred
+    pub exclude_ids: Vec<i64>,
+    /// Only the playlists with these exact titles are transferred
+    pub include_titles: Vec<String>,
+    /// The playlists with these exact titles are never transferred
+    pub exclude_titles: Vec<String>,
+    /// Only the playlists whose title matches one of these patterns are transferred, case
+    /// insensitive globs (e.g. "Road trip*") or regexes when prefixed with "re:"
+    pub include_patterns: Vec<String>,
+    /// The playlists whose title matches one of these patterns are never transferred
+    pub exclude_patterns: Vec<String>,
+    /// The user picks the playlists to transfer from a checklist, done by the frontend
+    pub pick: bool,
+}
+
+impl FilterArgs {
+    /// Adds the rules of `other` to these ones
+    pub fn extend(&mut self, other: &FilterArgs) {
+        self.include_ids.extend(&other.include_ids);
+        self.exclude_ids.extend(&other.exclude_ids);
+        self.include_titles.extend_from_slice(&other.include_titles);
+        self.exclude_titles.extend_from_slice(&other.exclude_titles);
+        self.include_patterns
+            .extend_from_slice(&other.include_patterns);
+        self.exclude_patterns
+            .extend_from_slice(&other.exclude_patterns);
+        self.pick |= other.pick;
+    }
+}
+
+#[derive(Debug)]
+enum Pattern {
+    Glob(GlobMatcher),
+    Regex(Regex),
+}
+
+impl Pattern {
+    fn parse(pattern: &str) -> Result<Self, String> {
+        if let Some(re) = pattern.strip_prefix("re:") {
+            return Regex::new(re)
+                .map(Pattern::Regex)
+                .map_err(|err| format!("Invalid playlist regex \"{re}\": {err}"));
+        }
+
+        GlobBuilder::new(pattern)
+            .case_insensitive(true)
+            .literal_separator(false)
+            .build()
+            .map(|glob| Pattern::Glob(glob.compile_matcher()))
+            .map_err(|err| format!("Invalid playlist glob \"{pattern}\": {err}"))
+    }
+
+    fn is_match(&self, title: &str) -> bool {
+        match self {
+            Pattern::Glob(glob) => glob.is_match(title),
+            Pattern::Regex(re) => re.is_match(title),
+        }
+    }
+}
+
+/// Compiled version of [`FilterArgs`] deciding which Deezer playlists get transferred
+#[derive(Debug, Default)]
+pub struct PlaylistFilter {
+    include_ids: Vec<i64>,
+    exclude_ids: Vec<i64>,
+    include_titles: Vec<String>,
+    exclude_titles: Vec<String>,
+    include_patterns: Vec<Pattern>,
+    exclude_patterns: Vec<Pattern>,
+}
+
+impl PlaylistFilter {
+    /// Compiles the rules, fails on an invalid pattern
+    pub fn new(args: &FilterArgs) -> Result<Self, String> {
+        let compile = |patterns: &[String]| {
+            patterns
+                .iter()
+                .map(|p| Pattern::parse(p))
+                .collect::<Result<Vec<_>, _>>()
+        };
+
+        Ok(Self {
+            include_ids: args.include_ids.clone(),
+            exclude_ids: args.exclude_ids.clone(),
+            include_titles: args.include_titles.clone(),
+            exclude_titles: args.exclude_titles.clone(),
+            include_patterns: compile(&args.include_patterns)?,
+            exclude_patterns: compile(&args.exclude_patterns)?,
+        })
+    }
+
+    fn has_include_rules(&self) -> bool {
+        !self.include_ids.is_empty()
+            || !self.include_titles.is_empty()
+            || !self.include_patterns.is_empty()
+    }
+
+    /// Exclusions always win, then a playlist is kept if there are no include rules or one of them matches
+    pub fn matches(&self, id: i64, title: &str) -> bool {
+        if self.exclude_ids.contains(&id)
+            || self.exclude_titles.iter().any(|t| t == title)
+            || self.exclude_patterns.iter().any(|p| p.is_match(title))
+        {
+            return false;
+        }
+
+        !self.has_include_rules()
+            || self.include_ids.contains(&id)
+            || self.include_titles.iter().any(|t| t == title)
+            || self.include_patterns.iter().any(|p| p.is_match(title))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FilterArgs, PlaylistFilter};
+
+    fn filter(args: FilterArgs) -> PlaylistFilter {
+        PlaylistFilter::new(&args).unwrap()
+    }
+
+    #[test]
+    fn matches_everything_without_rules() {
+        let filter = filter(FilterArgs::default());
+
+        assert!(filter.matches(1, "Road trip"));
+        assert!(filter.matches(0, ""));
+    }
+
+    #[test]
+    fn matches_any_include_rule() {
+        let filter = filter(FilterArgs {
+            include_ids: vec![1],
+            include_titles: vec!["Morning".to_owned()],
+            include_patterns: vec!["road trip*".to_owned(), "re:^\\d{4}$".to_owned()],
+            ..Default::default()
+        });
+
+        assert!(filter.matches(1, "Anything"));
+        assert!(filter.matches(2, "Morning"));
+        // Titles are exact, globs are case insensitive, regexes aren't anchored unless asked
+        assert!(!filter.matches(2, "morning"));
+        assert!(filter.matches(2, "Road Trip 2024"));
+        assert!(filter.matches(2, "2024"));
+        assert!(!filter.matches(2, "Summer 2024"));
+        assert!(!filter.matches(2, "Evening"));
+    }
+
+    #[test]
+    fn exclusions_win_over_inclusions() {
+        let filter = filter(FilterArgs {
+            include_patterns: vec!["*".to_owned()],
+            exclude_ids: vec![1],
+            exclude_titles: vec!["Loved tracks".to_owned()],
+            exclude_patterns: vec!["re:(?i)podcast".to_owned(), "drafts*".to_owned()],
+            ..Default::default()
+        });
+
+        assert!(filter.matches(2, "Road trip"));
+        assert!(!filter.matches(1, "Road trip"));
+        assert!(!filter.matches(2, "Loved tracks"));
+        assert!(!filter.matches(2, "My Podcasts"));
+        // Slashes aren't path separators in titles
+        assert!(!filter.matches(2, "Drafts/summer"));
+    }
+
+    #[test]
+    fn new_rejects_invalid_patterns() {
+        let err = PlaylistFilter::new(&FilterArgs {
+            include_patterns: vec!["re:(".to_owned()],
+            ..Default::default()
+        })
+        .unwrap_err();
+        assert!(err.starts_with("Invalid playlist regex \"(\""));
+
+        let err = PlaylistFilter::new(&FilterArgs {
+            exclude_patterns: vec!["[a-".to_owned()],
+            ..Default::default()
+        })
+        .unwrap_err();
+        assert!(err.starts_with("Invalid playlist glob \"[a-\""));
+    }
+}
@@ -0,0 +1,322 @@
+use std::{collections::HashSet, path::Path};
+
+use calamine::{open_workbook_auto, Data, Reader};
+
+use crate::deezer::{DeezerPlaylist, DeezerTrack};
+use crate::logger::{log, LogCategory};
+use crate::playlist_file::normalize_header;
+
+/// Rows searched for the header, the first ones may hold a title or notes
+const HEADER_ROWS: usize = 10;
+
+const TITLE: &[&str] = &[
+    "songtitle",
+    "tracktitle",
+    "songname",
+    "trackname",
+    "title",
+    "song",
+    "track",
+];
+const ARTIST: &[&str] = &["artist", "artistname", "artists", "songartist"];
+const ALBUM: &[&str] = &["albumtitle", "album", "albumname"];
+const ISRC: &[&str] = &["isrc"];
+const TRACK_ID: &[&str] = &["songid", "trackid"];
+const DURATION: &[&str] = &["duration", "length", "songduration"];
+const PLAYLIST: &[&str] = &["playlistname", "playlisttitle", "playlist"];
+const PLAYLIST_ID: &[&str] = &["playlistid"];
+
+/// What a sheet of the export holds, guessed from its name
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Sheet {
+    Favourites,
+    Playlists,
+    History,
+}
+
+impl Sheet {
+    fn from_name(name: &str) -> Option<Self> {
+        let name = name.to_lowercase();
+
+        if name.contains("history") {
+            Some(Sheet::History)
+        } else if name.contains("playlist") {
+            Some(Sheet::Playlists)
+        } else if name.contains("favo") && (name.contains("song") || name.contains("track")) {
+            Some(Sheet::Favourites)
+        } else {
+            None
+        }
+    }
+}
+
+/// Indexes of the columns of a sheet
+struct Columns {
+    title: usize,
+    artist: usize,
+    album: Option<usize>,
+    isrc: Option<usize>,
+    track_id: Option<usize>,
+    duration: Option<usize>,
+    playlist: Option<usize>,
+    playlist_id: Option<usize>,
+}
+
+impl Columns {
+    /// Finds the header row, returns the columns and the index of the first data row
+    fn find(rows: &[&[Data]]) -> Option<(Self, usize)> {
+        rows.iter()
+            .take(HEADER_ROWS)
+            .enumerate()
+            .find_map(|(i, row)| {
+                let headers = row
+                    .iter()
+                    .map(|cell| normalize_header(&cell.to_string()))
+                    .collect::<Vec<_>>();
+                // Aliases are in order of preference, `Song Title` wins over a `Song` column
+                let column = |aliases: &[&str]| {
+                    aliases
+                        .iter()
+                        .find_map(|alias| headers.iter().position(|h| h == alias))
+                };
+
+                let columns = Columns {
+                    title: column(TITLE)?,
+                    artist: column(ARTIST)?,
+                    album: column(ALBUM),
+                    isrc: column(ISRC),
+                    track_id: column(TRACK_ID),
+                    duration: column(DURATION),
+                    playlist: column(PLAYLIST),
+                    playlist_id: column(PLAYLIST_ID),
+                };
+
+                Some((columns, i + 1))
+            })
+    }
+}
+
+fn cell(row: &[Data], column: Option<usize>) -> Option<String> {
+    let value = row.get(column?)?.to_string();
+    let value = value.trim();
+
+    (!value.is_empty()).then(|| value.to_owned())
+}
+
+/// Seconds, or `mm:ss` / `hh:mm:ss`. Zero means unknown
+fn parse_duration(value: &str) -> Option<u64> {
+    if let Ok(seconds) = value.parse::<f64>() {
+        return (seconds > 0.0).then_some(seconds.round() as u64);
+    }
+
+    value
+        .split(':')
+        .try_fold(0, |total, part| {
+            part.trim().parse::<u64>().ok().map(|n| total * 60 + n)
+        })
+        .filter(|&seconds| seconds > 0)
+}
+
+fn track(row: &[Data], columns: &Columns) -> Option<DeezerTrack> {
+    Some(DeezerTrack {
+        id: cell(row, columns.track_id)
+            .and_then(|id| id.parse().ok())
+            .unwrap_or_default(),
+        title: cell(row, Some(columns.title))?,
+        artist_name: cell(row, Some(columns.artist)).unwrap_or_default(),
+        album_title: cell(row, columns.album),
+        duration: cell(row, columns.duration).and_then(|d| parse_duration(&d)),
+        isrc: cell(row, columns.isrc),
+        spotify_id: None,
+    })
+}
+
+/// Adds the track to the playlist with this id and title, creating it if needed
+fn push(playlists: &mut Vec<DeezerPlaylist>, id: i64, title: &str, track: DeezerTrack) {
+    match playlists
+        .iter_mut()
+        .find(|p| p.id == id && p.title == title)
+    {
+        Some(playlist) => playlist.tracks.push(track),
+        None => playlists.push(DeezerPlaylist {
+            id,
+            title: title.to_owned(),
+            tracks: vec![track],
+        }),
+    }
+}
+
+/// Reads the favourite tracks and the playlists of the personal data export of Deezer (the xlsx
+/// file of "My personal data" in the account settings), without the Deezer API.
+///
+/// The sheets are recognized by their names and their columns by their headers. Favourites become
+/// a "Favourite tracks" playlist. With `history`, the listened tracks become a "Listening history"
+/// playlist, each track once
+pub fn import(path: &Path, history: bool) -> Result<Vec<DeezerPlaylist>, String> {
+    let mut workbook = open_workbook_auto(path)
+        .map_err(|err| format!("Failed to open {}: {err}", path.display()))?;
+    let mut playlists: Vec<DeezerPlaylist> = Vec::new();
+    let mut listened: Vec<DeezerTrack> = Vec::new();
+    let mut seen = HashSet::new();
+
+    for name in workbook.sheet_names() {
+        let Some(sheet) = Sheet::from_name(&name) else {
+            continue;
+        };
+        if sheet == Sheet::History && !history {
+            continue;
+        }
+
+        let range = workbook.worksheet_range(&name).map_err(|err| {
+            format!(
+                "Failed to read the sheet {name} of {}: {err}",
+                path.display()
+            )
+        })?;
+        let rows = range.rows().collect::<Vec<_>>();
+
+        let Some((columns, start)) = Columns::find(&rows) else {
+            log!(
+                "Import",
+                LogCategory::Warn,
+                "Skipping the sheet {name}, it has no title and artist columns"
+            );
+            continue;
+        };
+
+        for row in &rows[start..] {
+            let Some(track) = track(row, &columns) else {
+                continue;
+            };
+
+            match sheet {
+                Sheet::Favourites => push(&mut playlists, 0, "Favourite tracks", track),
+                Sheet::Playlists => {
+                    let id = cell(row, columns.playlist_id)
+                        .and_then(|id| id.parse().ok())
+                        .unwrap_or_default();
+                    // Without a playlist column, the sheet is a single playlist
+                    let title = cell(row, columns.playlist).unwrap_or_else(|| name.clone());
+
+                    push(&mut playlists, id, &title, track);
+                }
+                Sheet::History => {
+                    let key = (track.title.to_lowercase(), track.artist_name.to_lowercase());
+
+                    if seen.insert(key) {
+                        listened.push(track);
+                    }
+                }
+            }
+        }
+    }
+
+    if !listened.is_empty() {
+        playlists.push(DeezerPlaylist {
+            id: 0,
+            title: String::from("Listening history"),
+            tracks: listened,
+        });
+    }
+
+    if playlists.is_empty() {
+        return Err(format!(
+            "No favourite tracks nor playlists found in {}, is it the Deezer personal data export?",
+            path.display()
+        ));
+    }
+
+    Ok(playlists)
+}
+
+#[cfg(test)]
+mod tests {
+    use calamine::Data;
+
+    use super::{parse_duration, Columns, Sheet};
+
+    fn row(cells: &[&str]) -> Vec<Data> {
+        cells
+            .iter()
+            .map(|cell| match cell {
+                &"" => Data::Empty,
+                cell => Data::String(cell.to_string()),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn sheet_from_name_guesses_the_content() {
+        assert_eq!(Sheet::from_name("favourite_songs"), Some(Sheet::Favourites));
+        assert_eq!(Sheet::from_name("Favorite Tracks"), Some(Sheet::Favourites));
+        assert_eq!(Sheet::from_name("Playlists"), Some(Sheet::Playlists));
+        assert_eq!(
+            Sheet::from_name("10_listeningHistory"),
+            Some(Sheet::History)
+        );
+        // History wins, it's listened tracks whatever they come from
+        assert_eq!(Sheet::from_name("playlist_history"), Some(Sheet::History));
+        assert_eq!(Sheet::from_name("favourite_artists"), None);
+        assert_eq!(Sheet::from_name("user_information"), None);
+    }
+
+    #[test]
+    fn columns_find_the_header_below_the_title_rows() {
+        let rows = [
+            row(&["Your Deezer data"]),
+            row(&[]),
+            row(&["", "", ""]),
+            row(&[
+                "Song",
+                "Song Title",
+                "Artist",
+                "Album Title",
+                "ISRC",
+                "Length",
+            ]),
+            row(&[
+                "1",
+                "One More Time",
+                "Daft Punk",
+                "Discovery",
+                "GBDUW0000059",
+                "5:20",
+            ]),
+        ];
+        let rows = rows.iter().map(Vec::as_slice).collect::<Vec<_>>();
+
+        let (columns, first) = Columns::find(&rows).unwrap();
+        assert_eq!(first, 4);
+        // `Song Title` is preferred over `Song`
+        assert_eq!(columns.title, 1);
+        assert_eq!(columns.artist, 2);
+        assert_eq!(columns.album, Some(3));
+        assert_eq!(columns.isrc, Some(4));
+        assert_eq!(columns.duration, Some(5));
+        assert_eq!(columns.track_id, None);
+        assert_eq!(columns.playlist, None);
+    }
+
+    #[test]
+    fn columns_need_a_title_and_an_artist() {
+        let rows = [
+            row(&["Song Title", "Album"]),
+            row(&["One More Time", "Discovery"]),
+        ];
+        let rows = rows.iter().map(Vec::as_slice).collect::<Vec<_>>();
+
+        assert!(Columns::find(&rows).is_none());
+    }
+
+    #[test]
+    fn parse_duration_reads_seconds_and_clock_times() {
+        assert_eq!(parse_duration("215"), Some(215));
+        assert_eq!(parse_duration("214.6"), Some(215));
+        assert_eq!(parse_duration("3:35"), Some(215));
+        assert_eq!(parse_duration("1:02:03"), Some(3723));
+        assert_eq!(parse_duration("0"), None);
+        assert_eq!(parse_duration("00:00"), None);
+        assert_eq!(parse_duration("3:xx"), None);
+        assert_eq!(parse_duration(""), None);
+    }
+}
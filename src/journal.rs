@@ -0,0 +1,213 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::spotify::SpotifyPlaylist;
+
+const JOURNAL_VERSION: u32 = 2;
+
+/// Progress of a playlist, each step is recorded once it's done
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct JournalEntry {
+    /// Deezer id of the playlist
+    pub deezer_id: i64,
+    /// Title of the Deezer playlist, before naming
+    pub title: String,
+    /// Set once every track is matched
+    pub matched: Option<SpotifyPlaylist>,
+    /// Set once the playlist is created on Spotify, or picked to be replaced
+    pub spotify_id: Option<String>,
+    /// Set once the playlist is created, or once the tracks of the replaced playlist are removed
+    pub cleared: bool,
+    /// How many tracks of `matched` are already added to the Spotify playlist
+    pub added: usize,
+    /// Set once every track is added
+    pub done: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct JournalFile {
+    version: u32,
+    source: String,
+    user_id: Option<String>,
+    playlists: Vec<JournalEntry>,
+}
+
+/// Progress of a transfer, saved after each step so a killed run can be resumed without creating
+/// the same playlists again. Without a file (the default), nothing is saved
+#[derive(Debug, Default)]
+pub struct Journal {
+    path: Option<PathBuf>,
+    /// What the playlists are transferred from, e.g. `deezer` or `import playlists.csv`
+    source: String,
+    /// The Spotify account the playlists are created on, set once the transfer starts
+    user_id: Option<String>,
+    /// Progress of each playlist of the transfer
+    pub playlists: Vec<JournalEntry>,
+}
+
+impl Journal {
+    /// `~/.local/share/deezer_to_spotify/journal.json` on Linux, the platform equivalent elsewhere
+    pub fn default_path() -> PathBuf {
+        dirs::data_local_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("deezer_to_spotify")
+            .join("journal.json")
+    }
+
+    /// Starts a new journal, refusing to overwrite the one of an unfinished transfer
+    pub fn start(path: &Path, source: &str) -> Result<Self, String> {
+        if path.exists() {
+            return Err(format!(
+                "A previous transfer didn't finish, continue it with --resume or remove {}",
+                path.display()
+            ));
+        }
+
+        Ok(Self {
+            path: Some(path.to_owned()),
+            source: source.to_owned(),
+            user_id: None,
+            playlists: Vec::new(),
+        })
+    }
+
+    /// Loads the journal of an unfinished transfer from the same source, a missing file means
+    /// there's nothing to resume
+    pub fn resume(path: &Path, source: &str) -> Result<Self, String> {
+        let mut journal = Self {
+            path: Some(path.to_owned()),
+            source: source.to_owned(),
+            user_id: None,
+            playlists: Vec::new(),
+        };
+
+        if !path.exists() {
+            return Ok(journal);
+        }
+
+        let json = std::fs::read_to_string(path)
+            .map_err(|err| format!("Failed to read the journal {}: {err}", path.display()))?;
+
+        let file: JournalFile = serde_json::from_str(&json)
+            .map_err(|err| format!("Failed to parse the journal {}: {err}", path.display()))?;
+
+        if file.version != JOURNAL_VERSION {
+            return Err(format!(
+                "The journal {} was made by another version, it can't be resumed",
+                path.display()
+            ));
+        }
+
+        if file.source != source {
+            return Err(format!(
+                "The journal {} is of a transfer from {}, not {source}, resume it with the same command or remove it",
+                path.display(),
+                file.source
+            ));
+        }
+
+        journal.user_id = file.user_id;
+        journal.playlists = file.playlists;
+
+        Ok(journal)
+    }
+
+    /// Records the Spotify account of the transfer, refusing to resume it on another one
+    pub fn set_user(&mut self, user_id: &str) -> Result<(), String> {
+        match &self.user_id {
+            Some(recorded) if recorded != user_id => Err(format!(
+                "The journal {} is of a transfer to the Spotify account {recorded}, log in with it to resume it or remove the journal",
+                self.path.as_deref().unwrap_or(Path::new("-")).display()
+            )),
+            Some(_) => Ok(()),
+            None => {
+                self.user_id = Some(user_id.to_owned());
+                self.save()
+            }
+        }
+    }
+
+    /// The progress of the playlist, `None` if it isn't recorded yet
+    pub fn get(&self, deezer_id: i64, title: &str) -> Option<&JournalEntry> {
+        self.playlists
+            .iter()
+            .find(|entry| entry.deezer_id == deezer_id && entry.title == title)
+    }
+
+    /// Records a step of the playlist and saves the journal
+    pub fn update(
+        &mut self,
+        deezer_id: i64,
+        title: &str,
+        step: impl FnOnce(&mut JournalEntry),
+    ) -> Result<(), String> {
+        let index = match self
+            .playlists
+            .iter()
+            .position(|entry| entry.deezer_id == deezer_id && entry.title == title)
+        {
+            Some(index) => index,
+            None => {
+                self.playlists.push(JournalEntry {
+                    deezer_id,
+                    title: title.to_owned(),
+                    matched: None,
+                    spotify_id: None,
+                    cleared: false,
+                    added: 0,
+                    done: false,
+                });
+                self.playlists.len() - 1
+            }
+        };
+
+        step(&mut self.playlists[index]);
+
+        self.save()
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|err| {
+                format!(
+                    "Failed to create the journal directory {}: {err}",
+                    dir.display()
+                )
+            })?;
+        }
+
+        let file = JournalFile {
+            version: JOURNAL_VERSION,
+            source: self.source.clone(),
+            user_id: self.user_id.clone(),
+            playlists: self.playlists.clone(),
+        };
+        let json = serde_json::to_string_pretty(&file)
+            .map_err(|err| format!("Failed to serialize the journal: {err}"))?;
+
+        // Written aside then renamed, so a kill while writing doesn't lose the previous steps
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, json)
+            .and_then(|_| std::fs::rename(&tmp, path))
+            .map_err(|err| format!("Failed to write the journal to {}: {err}", path.display()))
+    }
+
+    /// Removes the file once every playlist is done, it's kept for `--resume` otherwise
+    pub fn finish(&self) -> Result<(), String> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        if self.playlists.iter().all(|entry| entry.done) && path.exists() {
+            std::fs::remove_file(path)
+                .map_err(|err| format!("Failed to remove the journal {}: {err}", path.display()))?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,28 @@
+use serde_json::Value;
+
+/// The string at `pointer` (e.g. `/artists/0/name`) of the `answer` of a provider, an error if
+/// it's missing
+pub fn str_at(value: &Value, pointer: &str, answer: &str) -> Result<String, String> {
+    value
+        .pointer(pointer)
+        .and_then(Value::as_str)
+        .map(str::to_owned)
+        .ok_or_else(|| format!("Unexpected {answer} json result, {pointer} is missing"))
+}
+
+/// The integer at `pointer` of the `answer` of a provider, an error if it's missing
+pub fn i64_at(value: &Value, pointer: &str, answer: &str) -> Result<i64, String> {
+    value
+        .pointer(pointer)
+        .and_then(Value::as_i64)
+        .ok_or_else(|| format!("Unexpected {answer} json result, {pointer} is missing"))
+}
+
+/// The array at `pointer` of the `answer` of a provider, an error if it's missing
+pub fn array_at<'v>(value: &'v Value, pointer: &str, answer: &str) -> Result<&'v [Value], String> {
+    value
+        .pointer(pointer)
+        .and_then(Value::as_array)
+        .map(Vec::as_slice)
+        .ok_or_else(|| format!("Unexpected {answer} json result, {pointer} is missing"))
+}
@@ -0,0 +1,74 @@
+//! Transfer Deezer playlists to Spotify.
+//!
+//! Log in with [`deezer::Deezer`] and [`spotify::Spotify`] (see [`App::init`]), list and fetch the
+//! Deezer playlists, then match and create them on Spotify with the [`transfer`] functions.
+//! Nothing is printed: messages go to the sink set with [`logger::set_sink`], the steps of the
+//! transfers to the one set with [`progress::set_sink`].
+
+#![warn(missing_docs)]
+
+/// Spotify matches kept between runs
+pub mod cache;
+/// Settings of the config file and the environment
+pub mod config;
+/// Deezer client and models
+pub mod deezer;
+/// Selection of the Deezer playlists to transfer
+pub mod filter;
+/// Personal data export of Deezer
+pub mod gdpr;
+/// Progress of the transfers, to resume them
+pub mod journal;
+mod json;
+/// Messages of the library and where they go
+pub mod logger;
+/// Scoring of Spotify candidates against Deezer tracks
+pub mod matcher;
+mod oauth;
+/// Manual matches
+pub mod overrides;
+/// Dry runs, saved and applied later
+pub mod plan;
+/// Playlists exported to files
+pub mod playlist_file;
+/// Steps of the transfers, for progress displays
+pub mod progress;
+/// Tracks that need attention after a run
+pub mod report;
+/// Callback server of the OAuth logins
+pub mod server;
+/// Spotify client and models
+pub mod spotify;
+mod tokens;
+/// Matching and creation of the playlists on Spotify
+pub mod transfer;
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::server::Server;
+
+/// Time elapsed since the Unix epoch, zero if the clock is set before it
+pub fn unix_time() -> Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
+/// Login of a streaming service
+#[async_trait::async_trait]
+pub trait App {
+    /// Error of the login
+    type Error;
+
+    /// Logs in, with the saved tokens if possible, otherwise by waiting for the callback of the browser login
+    async fn init(&mut self, server: &Server) -> Result<(), Self::Error>;
+
+    /// Reuses the saved tokens if they're still valid, returns false if the user has to log in again
+    async fn restore_session(&mut self) -> bool;
+
+    /// Exchanges the authorization code of the callback for an access token
+    async fn fetch_token(&mut self, code: &str) -> Result<(), Self::Error>;
+
+    /// The page the user logs in on, sending `state` back with the callback
+    fn get_auth_url(&self, state: &str) -> String;
+}
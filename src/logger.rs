@@ -1,23 +1,195 @@
+use std::sync::{Mutex, OnceLock};
+
+use regex::Regex;
+
+/// Level of a message, from the most to the least important
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum LogCategory {
+    /// Something failed
+    Error,
+    /// Something to look at, the program goes on
+    Warn,
+    /// What the program is doing
     Info,
+    /// Info about something that went well
     Success,
+    /// Details to understand what the program did
+    Debug,
+    /// Every request and step
+    Trace,
+}
+
+impl LogCategory {
+    /// 0 for errors up to 4 for traces, `Success` is as verbose as `Info`
+    pub fn verbosity(self) -> u8 {
+        match self {
+            LogCategory::Error => 0,
+            LogCategory::Warn => 1,
+            LogCategory::Info | LogCategory::Success => 2,
+            LogCategory::Debug => 3,
+            LogCategory::Trace => 4,
+        }
+    }
+
+    /// Lowercase name, as written in the logs
+    pub fn name(self) -> &'static str {
+        match self {
+            LogCategory::Error => "error",
+            LogCategory::Warn => "warn",
+            LogCategory::Info => "info",
+            LogCategory::Success => "success",
+            LogCategory::Debug => "debug",
+            LogCategory::Trace => "trace",
+        }
+    }
+}
+
+/// What a message is about, set with `log!("Spotify", LogCategory::Info, { track: title }, ...)`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Fields<'a> {
+    /// Deezer or Spotify, the title of the messages of their clients by default
+    pub provider: Option<&'a str>,
+    /// Title of the playlist
+    pub playlist: Option<&'a str>,
+    /// Title of the track
+    pub track: Option<&'a str>,
+}
+
+/// A message of the library, tokens and secrets are already redacted
+#[derive(Debug, Clone, Copy)]
+pub struct Record<'a> {
+    /// What the message is about, e.g. `Spotify` or `Transfer`
+    pub title: &'a str,
+    /// Level of the message
+    pub category: LogCategory,
+    /// The message, already redacted
+    pub message: &'a str,
+    /// What the message is about, when known
+    pub fields: Fields<'a>,
+}
+
+type Sink = Box<dyn Fn(&Record) + Send + Sync>;
+
+static SINK: OnceLock<Sink> = OnceLock::new();
+static SECRETS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Sets where the messages go, they're dropped until it's called. Only the first call counts
+pub fn set_sink(sink: impl Fn(&Record) + Send + Sync + 'static) {
+    let _ = SINK.set(Box::new(sink));
+}
+
+/// Hides the secret in the messages from now on, e.g. an access token once it's known
+pub fn add_secret(secret: &str) {
+    if !secret.is_empty() {
+        SECRETS.lock().unwrap().push(secret.to_owned());
+    }
 }
 
+/// Hides the secrets given to [`add_secret`] and anything that looks like a token or a secret in
+/// URLs, headers and JSON bodies
+pub fn redact(s: &str) -> String {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    let pattern = PATTERN.get_or_init(|| {
+        Regex::new(
+            r#"((?:access_token|refresh_token|client_secret|secret|code)=|Bearer\s+|"(?:access_token|refresh_token)"\s*:\s*")[^&\s"']+"#,
+        )
+        .unwrap()
+    });
+
+    let mut redacted = pattern.replace_all(s, "${1}[redacted]").into_owned();
+
+    for secret in SECRETS.lock().unwrap().iter() {
+        redacted = redacted.replace(secret, "[redacted]");
+    }
+
+    redacted
+}
+
+#[doc(hidden)]
+pub fn emit(title: &str, category: LogCategory, fields: Fields, message: &str) {
+    let Some(sink) = SINK.get() else {
+        return;
+    };
+    let message = redact(message);
+    let provider = fields
+        .provider
+        .or_else(|| matches!(title, "Deezer" | "Spotify").then_some(title));
+
+    sink(&Record {
+        title,
+        category,
+        message: &message,
+        fields: Fields { provider, ..fields },
+    });
+}
+
+/// Sends a message to the sink, e.g. `log!("Spotify", LogCategory::Info, "Found {count} tracks")`,
+/// with fields: `log!("Spotify", LogCategory::Debug, { playlist: &title }, "Found {count} tracks")`
+#[macro_export]
 macro_rules! log {
+    ($title:tt, $cat:expr, { $($field:ident: $value:expr),* $(,)? }, $($args:tt)*) => ({
+        $crate::logger::emit(
+            $title,
+            $cat,
+            $crate::logger::Fields {
+                $($field: Some(::std::convert::AsRef::<str>::as_ref(&$value)),)*
+                ..::std::default::Default::default()
+            },
+            &format!($($args)*),
+        )
+    });
     ($title:tt, $cat:expr, $($args:tt)*) => ({
-        use std::io::Write;
-        use colored::Colorize;
+        $crate::logger::emit($title, $cat, $crate::logger::Fields::default(), &format!($($args)*))
+    });
+}
 
-        let title = match $cat {
-            LogCategory::Info => format!("[{}]", $title).purple(),
-            LogCategory::Success => format!("[{}]", $title).green(),
-        };
+pub use crate::log;
 
-        // Little hack to clear the stdout (current line) before writing to it.
-        // Had issues with the Loading crate holding the stdout.
-        print!("\r                                                                 ");
-        writeln!(&mut std::io::stdout(), "\r{title} {}", format!($($args)*)).unwrap();
-    })
-}
+#[cfg(test)]
+mod tests {
+    use super::{add_secret, redact};
+
+    #[test]
+    fn redact_hides_the_tokens_of_urls_and_headers() {
+        assert_eq!(
+            redact("GET /callback?state=abc&code=AQD-x_y.z&scope=read"),
+            "GET /callback?state=abc&code=[redacted]&scope=read"
+        );
+        assert_eq!(
+            redact("https://connect.deezer.com/oauth/access_token.php?app_id=1&secret=s3cr3t&code=fr1234"),
+            "https://connect.deezer.com/oauth/access_token.php?app_id=1&secret=[redacted]&code=[redacted]"
+        );
+        assert_eq!(
+            redact("https://api.deezer.com/user/me?access_token=frXyZ 200"),
+            "https://api.deezer.com/user/me?access_token=[redacted] 200"
+        );
+        assert_eq!(
+            redact("authorization: Bearer BQC4.abc-def"),
+            "authorization: Bearer [redacted]"
+        );
+        assert_eq!(
+            redact("grant_type=refresh_token&refresh_token=AQB&client_secret=xyz"),
+            "grant_type=refresh_token&refresh_token=[redacted]&client_secret=[redacted]"
+        );
+    }
 
-pub(crate) use log;
+    #[test]
+    fn redact_hides_the_tokens_of_json_bodies() {
+        assert_eq!(
+            redact(r#"{"access_token": "BQC4abc","token_type":"Bearer","refresh_token":"AQB"}"#),
+            r#"{"access_token": "[redacted]","token_type":"Bearer","refresh_token":"[redacted]"}"#
+        );
+    }
+
+    #[test]
+    fn redact_hides_the_added_secrets() {
+        add_secret("");
+        add_secret("e9f1c0ffee5ecre7");
+
+        assert_eq!(
+            redact("Failed with client e9f1c0ffee5ecre7: e9f1c0ffee5ecre7"),
+            "Failed with client [redacted]: [redacted]"
+        );
+        assert_eq!(redact("Found 12 tracks"), "Found 12 tracks");
+    }
+}
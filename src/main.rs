@@ -1,50 +1,495 @@
-mod deezer;
-mod logger;
-mod server;
-mod spotify;
+mod bars;
+mod cli;
 
+use std::{io::IsTerminal, path::PathBuf};
+
+use clap::{Parser, Subcommand};
 use dotenv::dotenv;
-use loading::Loading;
 use reqwest::Client;
 
-use crate::deezer::Deezer;
-use crate::server::Server;
-use crate::spotify::Spotify;
+use deezer_to_spotify::cache::MatchCache;
+use deezer_to_spotify::config::Config;
+use deezer_to_spotify::deezer::Deezer;
+use deezer_to_spotify::filter::PlaylistFilter;
+use deezer_to_spotify::gdpr;
+use deezer_to_spotify::journal::Journal;
+use deezer_to_spotify::logger::{log, LogCategory};
+use deezer_to_spotify::matcher::Matcher;
+use deezer_to_spotify::overrides::{best_choice, Overrides};
+use deezer_to_spotify::plan::Plan;
+use deezer_to_spotify::playlist_file::{self, ColumnMap, Format};
+use deezer_to_spotify::progress;
+use deezer_to_spotify::report::Report;
+use deezer_to_spotify::server::Server;
+use deezer_to_spotify::spotify::Spotify;
+use deezer_to_spotify::transfer::{self, Pending, Target};
+use deezer_to_spotify::App;
+
+use crate::cli::{CallbackArgs, FilterFlags, LogArgs};
+
+/// Import your Deezer playlists to Spotify
+#[derive(Parser, Debug)]
+#[command(version, about)]
+struct Cli {
+    /// Config file [default: config.toml in the user config directory, or $DTS_CONFIG]
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+
+    #[command(flatten)]
+    filter: FilterFlags,
+
+    #[command(flatten)]
+    callback: CallbackArgs,
+
+    #[command(flatten)]
+    log: LogArgs,
+
+    /// Where to write the report of unmatched and low confidence tracks
+    #[arg(long, default_value = "reports", global = true)]
+    report_dir: PathBuf,
+
+    /// Manual matches consulted before searching Spotify
+    #[arg(long, default_value = "overrides.toml", global = true)]
+    overrides: PathBuf,
 
-#[async_trait::async_trait]
-pub trait App {
-    type Error;
+    /// Where to keep the Spotify matches between runs [default: in the user cache directory]
+    #[arg(long, global = true)]
+    cache: Option<PathBuf>,
 
-    async fn init(&mut self);
+    /// Search every track again, without reading nor updating the match cache
+    #[arg(long, global = true)]
+    no_cache: bool,
 
-    async fn fetch_token(&mut self) -> Result<(), Self::Error>;
+    /// Where the progress of the transfer is recorded [default: in the user data directory]
+    #[arg(long, global = true)]
+    journal: Option<PathBuf>,
 
-    fn get_auth_url() -> String;
+    /// Continue the transfer recorded in the journal, skipping the playlists already created
+    #[arg(long, global = true)]
+    resume: bool,
+
+    /// Replace the tracks of the Spotify playlists that already have the name of a transferred
+    /// playlist, instead of creating new ones. Their tracks that aren't on Deezer are removed
+    #[arg(long, global = true)]
+    replace_existing: bool,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Match your Deezer playlists on Spotify and save what the transfer would do, without changing anything
+    Plan {
+        /// Where to write the plan
+        #[arg(short, long, default_value = "dts-plan.json")]
+        output: PathBuf,
+    },
+    /// Execute a plan previously saved with `plan`
+    Apply {
+        /// The plan to execute
+        #[arg(default_value = "dts-plan.json")]
+        plan: PathBuf,
+    },
+    /// Add manual matches to the overrides file from an unmatched-track report
+    Overrides {
+        /// The JSON report written by a previous run
+        report: PathBuf,
+        /// Use the best candidate of each track without asking
+        #[arg(long)]
+        accept_best: bool,
+    },
+    /// Save your Deezer playlists to files, without Spotify
+    Export {
+        /// Format of the files: json, csv, m3u8 or xspf
+        #[arg(short, long, default_value = "json")]
+        format: Format,
+        /// Directory where the files are written
+        #[arg(short, long, default_value = "export")]
+        output: PathBuf,
+        /// Write every playlist in a single file (JSON and CSV only)
+        #[arg(long)]
+        bundle: bool,
+    },
+    /// Transfer playlists from a file made by `export`, by another tool (Exportify, TuneMyMusic,
+    /// Soundiiz...) or by hand instead of Deezer
+    Import {
+        /// JSON, CSV, M3U8 or XSPF file
+        file: PathBuf,
+        /// Format of the file: json, csv, m3u8 or xspf [default: guessed from its extension]
+        #[arg(short, long)]
+        format: Option<Format>,
+        /// Headers of the CSV columns that aren't recognized, e.g. `title=Song,artist=Performer`.
+        /// Fields: playlist, title, artist, album, isrc, spotify (track URI, link or id), duration
+        #[arg(long, value_name = "FIELD=COLUMN,...")]
+        columns: Option<ColumnMap>,
+    },
+    /// Transfer your favourite tracks and playlists from the personal data export of Deezer
+    /// (the xlsx file of "My personal data" in the account settings), without the Deezer API
+    Gdpr {
+        /// The xlsx file of the export
+        file: PathBuf,
+        /// Also transfer the listened tracks, as a "Listening history" playlist
+        #[arg(long)]
+        history: bool,
+    },
+    /// Inspect or clean the match cache
+    Cache {
+        #[command(subcommand)]
+        action: CacheAction,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum CacheAction {
+    /// List the cached matches
+    Show,
+    /// Remove the matches older than the given number of days
+    Prune {
+        #[arg(long, value_name = "DAYS")]
+        older_than: u64,
+    },
+    /// Remove every cached match
+    Clear,
+}
+
+fn exit_with(err: String) -> ! {
+    log!("Error", LogCategory::Error, "{err}");
+    std::process::exit(1);
 }
 
 #[tokio::main]
 async fn main() {
-    dotenv().expect("Failed to load .env file");
+    let cli = Cli::parse();
+
+    // Nothing can be logged before, so its errors are printed directly
+    if let Err(err) = cli::init_logging(&cli.log) {
+        eprintln!("{err}");
+        std::process::exit(1);
+    }
+    progress::set_sink(bars::on_progress);
+
+    // The .env file is optional, its variables are read like the other environment variables
+    let _ = dotenv();
+
+    let config_path = cli
+        .config
+        .clone()
+        .or_else(|| std::env::var_os("DTS_CONFIG").map(PathBuf::from));
+    let mut config = Config::load(config_path.as_deref()).unwrap_or_else(|err| exit_with(err));
+
+    cli.filter.apply(&mut config.filters);
+    config.naming.replace_existing |= cli.replace_existing;
+    cli.callback.apply(&mut config.callback);
+    config.validate().unwrap_or_else(|err| exit_with(err));
+
+    let filter = PlaylistFilter::new(&config.filters).unwrap_or_else(|err| exit_with(err));
+    let mut overrides = Overrides::load(&cli.overrides).unwrap_or_else(|err| exit_with(err));
+
+    let cache_path = cli.cache.clone().unwrap_or_else(MatchCache::default_path);
+
+    if let Some(Command::Cache { action }) = &cli.command {
+        let cache = MatchCache::load(&cache_path).unwrap_or_else(|err| exit_with(err));
+
+        match action {
+            CacheAction::Show => cache.inspect(),
+            CacheAction::Prune { older_than } => {
+                let removed = cache.prune(older_than.saturating_mul(86400));
+                log!(
+                    "Cache",
+                    LogCategory::Success,
+                    "Removed {removed} cached match(es)"
+                );
+            }
+            CacheAction::Clear => {
+                cache.clear();
+                log!("Cache", LogCategory::Success, "Cleared the match cache");
+            }
+        }
+
+        cache.save().unwrap_or_else(|err| exit_with(err));
+
+        return;
+    }
+
+    if let Some(Command::Overrides {
+        report,
+        accept_best,
+    }) = &cli.command
+    {
+        let report = Report::load(report).unwrap_or_else(|err| exit_with(err));
+
+        if !accept_best && (!std::io::stdin().is_terminal() || !std::io::stderr().is_terminal()) {
+            exit_with(String::from(
+                "Not running in a terminal, use --accept-best to add the best candidates without asking",
+            ));
+        }
+
+        let added = if *accept_best {
+            overrides.add_from_report(&report, best_choice)
+        } else {
+            overrides.add_from_report(&report, cli::ask_override)
+        }
+        .unwrap_or_else(|err| exit_with(err));
+        overrides
+            .save(&cli.overrides)
+            .unwrap_or_else(|err| exit_with(err));
 
+        log!(
+            "Overrides",
+            LogCategory::Success,
+            "Added {added} override(s) to {}",
+            cli.overrides.display()
+        );
+
+        return;
+    }
+
+    let needs_deezer = !matches!(
+        cli.command,
+        Some(Command::Apply { .. } | Command::Import { .. } | Command::Gdpr { .. })
+    );
+    let needs_spotify = !matches!(cli.command, Some(Command::Export { .. }));
     let reqwest_client = Client::new();
-    let mut deezer = Deezer::new(&reqwest_client);
-    let mut spotify = Spotify::new(&reqwest_client);
 
-    Server::run().await;
+    if let Some(Command::Export { format, bundle, .. }) = &cli.command {
+        if *bundle && !format.can_bundle() {
+            exit_with(format!(
+                "A {} file can only hold one playlist, remove --bundle",
+                format.extension()
+            ));
+        }
+    }
 
-    deezer.init().await;
-    spotify.init().await;
+    // Checked before logging in, an unfinished transfer has to be resumed or removed first
+    let journal_path = cli.journal.clone().unwrap_or_else(Journal::default_path);
+    let source = match &cli.command {
+        None => Some(String::from("deezer")),
+        Some(Command::Import { file, .. }) => Some(format!("import {}", file.display())),
+        Some(Command::Gdpr { file, .. }) => Some(format!("gdpr {}", file.display())),
+        _ => None,
+    };
+    let mut journal = match &source {
+        Some(source) if cli.resume => Journal::resume(&journal_path, source),
+        Some(source) => Journal::start(&journal_path, source),
+        None => Ok(Journal::default()),
+    }
+    .unwrap_or_else(|err| exit_with(err));
 
-    let loader = Loading::default();
-    loader.text("Importing your Deezer playlists to Spotify...");
+    if !journal.playlists.is_empty() {
+        log!(
+            "Transfer",
+            LogCategory::Info,
+            "Resuming the transfer recorded in {}",
+            journal_path.display()
+        );
+    }
 
-    let deez_playlists = deezer.get_playlists().await.unwrap();
-    let new_playlists = spotify
-        .get_tracks_from_deezer(deez_playlists)
+    // Read before logging in, so a broken file fails right away
+    let imported = match &cli.command {
+        Some(Command::Import {
+            file,
+            format,
+            columns,
+        }) => Some(
+            playlist_file::import(file, *format, &columns.clone().unwrap_or_default())
+                .unwrap_or_else(|err| exit_with(err)),
+        ),
+        Some(Command::Gdpr { file, history }) => {
+            Some(gdpr::import(file, *history).unwrap_or_else(|err| exit_with(err)))
+        }
+        _ => None,
+    };
+
+    // Before opening any login page
+    cli::ask_missing_credentials(&mut config, &reqwest_client, needs_deezer, needs_spotify)
         .await
-        .unwrap();
-    spotify.create_playlists(new_playlists).await.unwrap();
+        .unwrap_or_else(|err| exit_with(err));
+
+    let mut deezer = Deezer::new(&reqwest_client, config.deezer.clone());
+    let mut spotify = Spotify::new(&reqwest_client, config.spotify.clone());
+
+    let server = if config.callback.headless {
+        Server::headless(&config.callback, cli::prompt)
+    } else {
+        Ok(Server::new(&config.callback, cli::prompt))
+    }
+    .unwrap_or_else(|err| exit_with(err));
+
+    if let Some(Command::Apply { plan }) = cli.command {
+        let plan = Plan::load(&plan).unwrap_or_else(|err| exit_with(err));
+
+        spotify
+            .init(&server)
+            .await
+            .unwrap_or_else(|err| exit_with(err));
+
+        plan.summary();
+        plan.apply(&spotify)
+            .await
+            .unwrap_or_else(|err| exit_with(err));
+
+        return;
+    }
+
+    if needs_deezer {
+        deezer
+            .init(&server)
+            .await
+            .unwrap_or_else(|err| exit_with(err));
+    }
+    if needs_spotify {
+        spotify
+            .init(&server)
+            .await
+            .unwrap_or_else(|err| exit_with(err));
+    }
+
+    let pending = if let Some(imported) = imported {
+        let mut selected = imported
+            .into_iter()
+            .filter(|playlist| filter.matches(playlist.id, &playlist.title))
+            .collect();
+
+        if config.filters.pick {
+            selected = cli::pick_playlists(selected, |playlist| &playlist.title)
+                .unwrap_or_else(|err| exit_with(err));
+        }
+
+        selected.into_iter().map(Pending::Read).collect::<Vec<_>>()
+    } else {
+        let mut selected = deezer
+            .list_playlists(&filter)
+            .await
+            .unwrap_or_else(|err| exit_with(err));
+
+        if config.filters.pick {
+            selected = cli::pick_playlists(selected, |playlist| &playlist.title)
+                .unwrap_or_else(|err| exit_with(err));
+        }
+
+        selected.into_iter().map(Pending::Deezer).collect()
+    };
+
+    if let Some(Command::Export {
+        format,
+        output,
+        bundle,
+    }) = &cli.command
+    {
+        let mut deez_playlists = Vec::new();
+
+        for playlist in pending {
+            let mut playlist = playlist
+                .fetch(&deezer)
+                .await
+                .unwrap_or_else(|err| exit_with(err));
+
+            // The exports keep the ISRCs, to match the tracks when they're imported
+            deezer.fill_isrcs(&mut playlist).await;
+            deez_playlists.push(playlist);
+        }
+
+        let files = playlist_file::export(&deez_playlists, *format, output, *bundle)
+            .unwrap_or_else(|err| exit_with(err));
+
+        log!(
+            "Export",
+            LogCategory::Success,
+            "Exported {} playlist(s) to {} file(s) in {}",
+            deez_playlists.len(),
+            files.len(),
+            output.display()
+        );
+
+        return;
+    }
+
+    let cache = if cli.no_cache {
+        MatchCache::default()
+    } else {
+        MatchCache::load(&cache_path).unwrap_or_else(|err| exit_with(err))
+    };
+    let matcher = Matcher::new(
+        config.matching,
+        config.concurrency.searches,
+        overrides,
+        cache,
+    );
+    let mut report = Report::new();
+    let mut plan = match &cli.command {
+        Some(Command::Plan { .. }) => Some(
+            Plan::new(&spotify, config.naming.replace_existing)
+                .await
+                .unwrap_or_else(|err| exit_with(err)),
+        ),
+        _ => None,
+    };
+    let target = match &mut plan {
+        Some(plan) => Target::Plan(plan),
+        None => Target::Spotify(&mut journal),
+    };
+
+    let outcomes = transfer::transfer(
+        &deezer,
+        &spotify,
+        pending,
+        &matcher,
+        &config.naming,
+        &mut report,
+        target,
+    )
+    .await
+    .unwrap_or_else(|err| exit_with(err));
+
+    if !report.is_empty() {
+        let (json, csv) = report
+            .write(&cli.report_dir)
+            .unwrap_or_else(|err| exit_with(err));
+
+        log!(
+            "Report",
+            LogCategory::Info,
+            "{} track(s) need attention, see {} or {}",
+            report.entries.len(),
+            json.display(),
+            csv.display()
+        );
+    }
+
+    // Each failure was logged when it happened
+    let failed = outcomes
+        .iter()
+        .filter(|outcome| outcome.result.is_err())
+        .count();
+
+    if let (Some(plan), Some(Command::Plan { output })) = (&plan, &cli.command) {
+        plan.save(output).unwrap_or_else(|err| exit_with(err));
+
+        log!(
+            "Plan",
+            LogCategory::Success,
+            "Plan saved to {}",
+            output.display()
+        );
+        plan.summary();
+    } else if failed == 0 {
+        log!(
+            "Transfer",
+            LogCategory::Success,
+            "Your Deezer playlists are now imported to Spotify!"
+        );
+    } else {
+        log!(
+            "Transfer",
+            LogCategory::Warn,
+            "{} of {} playlist(s) imported to Spotify, run again with --resume to retry the others",
+            outcomes.len() - failed,
+            outcomes.len()
+        );
+    }
 
-    loader.success("Your Deezer playlists are now imported to Spotify!");
-    loader.end();
+    if failed > 0 {
+        std::process::exit(1);
+    }
 }
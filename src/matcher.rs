@@ -0,0 +1,591 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Mutex,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::cache::MatchCache;
+use crate::deezer::{Deezer, DeezerTrack};
+use crate::logger::{log, LogCategory};
+use crate::overrides::{OverrideTarget, Overrides};
+use crate::spotify::{Spotify, SpotifyTrack};
+
+const CANDIDATES_PER_QUERY: u32 = 5;
+const REJECTED_KEPT: usize = 3;
+
+/// Minimum scores (between 0 and 1) a Spotify candidate needs to be used
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(default, deny_unknown_fields)]
+pub struct Thresholds {
+    /// Below this, the best candidate is rejected and the track is unmatched
+    pub accept: f64,
+    /// Below this, the track is matched but flagged as low confidence in the report
+    pub confident: f64,
+}
+
+impl Default for Thresholds {
+    fn default() -> Self {
+        Self {
+            accept: 0.6,
+            confident: 0.85,
+        }
+    }
+}
+
+/// A track returned by a Spotify search, with what's needed to compare it to a Deezer track
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Candidate {
+    /// The track found by the search
+    pub track: SpotifyTrack,
+    /// Title of its album
+    pub album_title: Option<String>,
+    /// Duration of the track, in milliseconds
+    pub duration_ms: Option<u64>,
+    /// International Standard Recording Code of the track
+    pub isrc: Option<String>,
+}
+
+/// A candidate with its score against the Deezer track
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ScoredCandidate {
+    /// The candidate
+    pub candidate: Candidate,
+    /// Between 0 and 1, 1 being a perfect match
+    pub score: f64,
+}
+
+/// Where a match comes from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MatchSource {
+    /// Found by searching Spotify
+    Search,
+    /// Matched during a previous run
+    Cache,
+    /// Set in the overrides file
+    Override,
+    /// The Spotify track given by the imported file
+    Imported,
+    /// An override says not to transfer the track
+    Skipped,
+}
+
+/// The outcome of the matching of a Deezer track
+#[derive(Debug, Clone)]
+pub struct MatchResult {
+    /// Where the match comes from
+    pub source: MatchSource,
+    /// The accepted Spotify track, `None` when no candidate reached the accept threshold
+    pub track: Option<SpotifyTrack>,
+    /// Score of the best candidate, 0 if none were found
+    pub confidence: f64,
+    /// The searches that were sent to Spotify, empty if none was needed
+    pub queries: Vec<String>,
+    /// Best candidates that weren't picked, highest score first
+    pub rejected: Vec<ScoredCandidate>,
+}
+
+impl MatchResult {
+    fn known(source: MatchSource, track: Option<SpotifyTrack>, confidence: f64) -> Self {
+        Self {
+            source,
+            track,
+            confidence,
+            queries: Vec::new(),
+            rejected: Vec::new(),
+        }
+    }
+
+    /// True if a track was accepted with a score below the confident threshold
+    pub fn is_low_confidence(&self, thresholds: &Thresholds) -> bool {
+        self.track.is_some() && self.confidence < thresholds.confident
+    }
+}
+
+/// Finds the Spotify equivalent of Deezer tracks
+#[derive(Debug, Default)]
+pub struct Matcher {
+    /// Scores needed to accept a match and to stop searching
+    pub thresholds: Thresholds,
+    /// How many tracks are searched at the same time
+    pub searches: usize,
+    /// Manual matches, looked at first
+    pub overrides: Overrides,
+    /// Matches of the previous runs
+    pub cache: MatchCache,
+    /// Results of this run, so a track found in several playlists is only searched once
+    results: Mutex<HashMap<String, MatchResult>>,
+}
+
+/// Identifies the tracks found in several playlists
+pub(crate) fn track_key(track: &DeezerTrack) -> String {
+    if track.id != 0 {
+        return track.id.to_string();
+    }
+
+    format!(
+        "{}|{}|{}|{}|{}",
+        track.title.to_lowercase(),
+        track.artist_name.to_lowercase(),
+        track
+            .album_title
+            .as_deref()
+            .unwrap_or_default()
+            .to_lowercase(),
+        track.isrc.as_deref().unwrap_or_default(),
+        track.spotify_id.as_deref().unwrap_or_default()
+    )
+}
+
+impl Matcher {
+    /// A matcher sending up to `searches` searches at the same time
+    pub fn new(
+        thresholds: Thresholds,
+        searches: usize,
+        overrides: Overrides,
+        cache: MatchCache,
+    ) -> Self {
+        Self {
+            thresholds,
+            searches,
+            overrides,
+            cache,
+            results: Mutex::default(),
+        }
+    }
+
+    /// Uses the override, the Spotify track of the imported file or the cached match for the track if
+    /// there's one, otherwise searches from the most to the least precise query, stopping as soon as
+    /// a confident candidate shows up. Tracks already found during this run aren't searched again.
+    ///
+    /// With `deezer`, a missing ISRC is fetched before searching, only for the tracks that are neither
+    /// overridden nor cached by their Deezer id
+    pub async fn find(
+        &self,
+        spotify: &Spotify<'_>,
+        deezer: Option<&Deezer<'_>>,
+        track: &DeezerTrack,
+    ) -> Result<MatchResult, String> {
+        let key = track_key(track);
+
+        if let Some(result) = self.results.lock().unwrap().get(&key) {
+            return Ok(result.clone());
+        }
+
+        let result = self.search(spotify, deezer, track).await?;
+        self.results.lock().unwrap().insert(key, result.clone());
+
+        Ok(result)
+    }
+
+    async fn search(
+        &self,
+        spotify: &Spotify<'_>,
+        deezer: Option<&Deezer<'_>>,
+        track: &DeezerTrack,
+    ) -> Result<MatchResult, String> {
+        if let Some(result) = self.known(spotify, track).await? {
+            return Ok(result);
+        }
+
+        // Overrides and cached matches may be keyed by the ISRC, checked again once it's known
+        let with_isrc;
+        let track = match deezer {
+            Some(deezer) if track.isrc.is_none() => match deezer.get_isrc(track.id).await {
+                Some(isrc) => {
+                    with_isrc = DeezerTrack {
+                        isrc: Some(isrc),
+                        ..track.clone()
+                    };
+
+                    if let Some(result) = self.known(spotify, &with_isrc).await? {
+                        return Ok(result);
+                    }
+
+                    &with_isrc
+                }
+                None => track,
+            },
+            _ => track,
+        };
+
+        self.search_spotify(spotify, track).await
+    }
+
+    /// The match of the track that doesn't need a search: overridden, imported or cached
+    async fn known(
+        &self,
+        spotify: &Spotify<'_>,
+        track: &DeezerTrack,
+    ) -> Result<Option<MatchResult>, String> {
+        match self.overrides.get(track) {
+            Some(OverrideTarget::Skip) => {
+                return Ok(Some(MatchResult::known(MatchSource::Skipped, None, 1.0)))
+            }
+            // A wrong override leaves the track unmatched, it's fixed in the overrides file
+            Some(OverrideTarget::Track(id)) => {
+                let found = match spotify.get_track(&id).await {
+                    Ok(found) => Some(found),
+                    Err(err) => {
+                        log!(
+                            "Match",
+                            LogCategory::Warn,
+                            { track: track.title },
+                            "The override of \"{}\" by \"{}\" points to the Spotify track {id}, which can't be fetched: {err}",
+                            track.title,
+                            track.artist_name
+                        );
+                        None
+                    }
+                };
+                let confidence = if found.is_some() { 1.0 } else { 0.0 };
+
+                return Ok(Some(MatchResult::known(
+                    MatchSource::Override,
+                    found,
+                    confidence,
+                )));
+            }
+            None => {}
+        }
+
+        // The track may have been removed from Spotify since the file was made
+        if let Some(id) = &track.spotify_id {
+            match spotify.get_track(id).await {
+                Ok(found) => {
+                    return Ok(Some(MatchResult::known(
+                        MatchSource::Imported,
+                        Some(found),
+                        1.0,
+                    )))
+                }
+                Err(err) => log!(
+                    "Match",
+                    LogCategory::Warn,
+                    { track: track.title },
+                    "Searching \"{}\" instead of using its imported Spotify track {id}: {err}",
+                    track.title
+                ),
+            }
+        }
+
+        // Matches cached with a lower accept threshold are searched again
+        if let Some(entry) = self
+            .cache
+            .get(track)
+            .filter(|entry| entry.confidence >= self.thresholds.accept)
+        {
+            log!(
+                "Match",
+                LogCategory::Debug,
+                { track: track.title },
+                "Found \"{}\" in the match cache",
+                track.title
+            );
+            return Ok(Some(MatchResult::known(
+                MatchSource::Cache,
+                Some(entry.track),
+                entry.confidence,
+            )));
+        }
+
+        Ok(None)
+    }
+
+    async fn search_spotify(
+        &self,
+        spotify: &Spotify<'_>,
+        track: &DeezerTrack,
+    ) -> Result<MatchResult, String> {
+        let mut queries = Vec::new();
+        let mut scored: Vec<ScoredCandidate> = Vec::new();
+
+        for query in search_queries(track) {
+            let candidates = spotify.search_tracks(&query, CANDIDATES_PER_QUERY).await?;
+            log!(
+                "Match",
+                LogCategory::Debug,
+                { track: track.title },
+                "Searched {query}: {} candidate(s)",
+                candidates.len()
+            );
+            queries.push(query);
+
+            for candidate in candidates {
+                if scored
+                    .iter()
+                    .any(|s| s.candidate.track.id == candidate.track.id)
+                {
+                    continue;
+                }
+
+                let score = score(track, &candidate);
+                scored.push(ScoredCandidate { candidate, score });
+            }
+
+            if scored.iter().any(|s| s.score >= self.thresholds.confident) {
+                break;
+            }
+        }
+
+        scored.sort_by(|a, b| b.score.total_cmp(&a.score));
+
+        let confidence = scored.first().map(|s| s.score).unwrap_or_default();
+        let found = if confidence >= self.thresholds.accept {
+            Some(scored.remove(0).candidate.track)
+        } else {
+            None
+        };
+        scored.truncate(REJECTED_KEPT);
+
+        if let Some(found) = &found {
+            self.cache.insert(track, found, confidence);
+        }
+
+        Ok(MatchResult {
+            source: MatchSource::Search,
+            track: found,
+            confidence,
+            queries,
+            rejected: scored,
+        })
+    }
+}
+
+fn search_queries(track: &DeezerTrack) -> Vec<String> {
+    let mut queries = Vec::new();
+
+    if let Some(isrc) = &track.isrc {
+        queries.push(format!("isrc:{isrc}"));
+    }
+
+    queries.push(format!(
+        "track:\"{}\" artist:\"{}\"",
+        track.title.replace('"', ""),
+        track.artist_name.replace('"', "")
+    ));
+    queries.push(format!(
+        "{} {}",
+        normalize(&track.title),
+        normalize(&track.artist_name)
+    ));
+
+    queries
+}
+
+/// Lowercases, drops bracketed parts like "(feat. X)" or "[Remastered]" and anything that isn't alphanumeric
+fn normalize(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut depth = 0usize;
+
+    // "Title - Remastered 2011" style suffixes
+    let title = s.split(" - ").next().unwrap_or(s);
+
+    for c in title.chars() {
+        match c {
+            '(' | '[' => depth += 1,
+            ')' | ']' => depth = depth.saturating_sub(1),
+            _ if depth > 0 => {}
+            c if c.is_alphanumeric() => out.extend(c.to_lowercase()),
+            _ => out.push(' '),
+        }
+    }
+
+    let out = out.split_whitespace().collect::<Vec<_>>().join(" ");
+
+    if out.is_empty() {
+        s.to_lowercase()
+    } else {
+        out
+    }
+}
+
+/// Dice coefficient of the normalized words of both strings
+fn similarity(a: &str, b: &str) -> f64 {
+    let a = normalize(a);
+    let b = normalize(b);
+    let a = a.split(' ').collect::<HashSet<_>>();
+    let b = b.split(' ').collect::<HashSet<_>>();
+
+    if a.is_empty() || b.is_empty() {
+        return 0.0;
+    }
+
+    2.0 * a.intersection(&b).count() as f64 / (a.len() + b.len()) as f64
+}
+
+fn score(track: &DeezerTrack, candidate: &Candidate) -> f64 {
+    if track.isrc.is_some() && track.isrc == candidate.isrc {
+        return 1.0;
+    }
+
+    let title = similarity(&track.title, &candidate.track.title);
+    let artist = similarity(&track.artist_name, &candidate.track.artist_name);
+
+    match (track.duration, candidate.duration_ms) {
+        (Some(secs), Some(ms)) => {
+            let diff = (secs as f64 - ms as f64 / 1000.0).abs();
+            let duration = (1.0 - (diff - 2.0).max(0.0) / 28.0).clamp(0.0, 1.0);
+
+            title * 0.55 + artist * 0.3 + duration * 0.15
+        }
+        _ => title * 0.65 + artist * 0.35,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{normalize, score, search_queries, similarity, Candidate, Thresholds};
+    use crate::deezer::DeezerTrack;
+    use crate::spotify::SpotifyTrack;
+
+    fn track(title: &str, artist: &str, duration: Option<u64>, isrc: Option<&str>) -> DeezerTrack {
+        DeezerTrack {
+            id: 1,
+            title: title.to_owned(),
+            artist_name: artist.to_owned(),
+            album_title: None,
+            duration,
+            isrc: isrc.map(str::to_owned),
+            spotify_id: None,
+        }
+    }
+
+    fn candidate(
+        title: &str,
+        artist: &str,
+        duration_ms: Option<u64>,
+        isrc: Option<&str>,
+    ) -> Candidate {
+        Candidate {
+            track: SpotifyTrack {
+                id: String::from("0DiWol3AO6WpXZgp0goxAV"),
+                title: title.to_owned(),
+                artist_name: artist.to_owned(),
+            },
+            album_title: None,
+            duration_ms,
+            isrc: isrc.map(str::to_owned),
+        }
+    }
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-9, "{a} != {b}");
+    }
+
+    #[test]
+    fn normalize_drops_the_brackets_and_suffixes() {
+        assert_eq!(
+            normalize("One More Time (feat. Romanthony) [Radio Edit]"),
+            "one more time"
+        );
+        assert_eq!(normalize("Hey Jude - Remastered 2015"), "hey jude");
+        assert_eq!(normalize("Don't Stop Me Now!"), "don t stop me now");
+        assert_eq!(normalize("Beyoncé"), "beyoncé");
+        // Nothing left, the whole title is kept
+        assert_eq!(normalize("(Intro)"), "(intro)");
+    }
+
+    #[test]
+    fn similarity_compares_the_words() {
+        assert_close(similarity("Hey Jude", "Hey Jude - Remastered 2009"), 1.0);
+        assert_close(similarity("Digital Love", "digital love (live)"), 1.0);
+        assert_close(similarity("One More Time", "One Time"), 0.8);
+        assert_close(similarity("Teardrop", "Angel"), 0.0);
+    }
+
+    #[test]
+    fn score_trusts_the_isrc() {
+        let track = track(
+            "One More Time",
+            "Daft Punk",
+            Some(320),
+            Some("GBDUW0000059"),
+        );
+
+        assert_close(
+            score(
+                &track,
+                &candidate("Something else", "Someone", None, Some("GBDUW0000059")),
+            ),
+            1.0,
+        );
+        assert!(
+            score(
+                &track,
+                &candidate("Something else", "Someone", None, Some("GBDUW0000060"))
+            ) < 0.1
+        );
+    }
+
+    #[test]
+    fn score_weights_the_duration() {
+        let track = track("One More Time", "Daft Punk", Some(320), None);
+        let with = |ms| score(&track, &candidate("One More Time", "Daft Punk", ms, None));
+
+        // Up to 2 seconds apart is the same recording, then it decreases until 30 seconds
+        assert_close(with(Some(322_000)), 1.0);
+        assert_close(with(Some(336_000)), 0.925);
+        assert_close(with(Some(400_000)), 0.85);
+        // Without duration, only the title and the artist count
+        assert_close(with(None), 1.0);
+    }
+
+    #[test]
+    fn score_falls_around_the_thresholds() {
+        let thresholds = Thresholds::default();
+        let track = track("One More Time", "Daft Punk", None, None);
+        let timed = DeezerTrack {
+            duration: Some(320),
+            ..track.clone()
+        };
+
+        // Confident: the same title and artist
+        let exact = score(
+            &track,
+            &candidate("One More Time - Remastered", "Daft Punk", None, None),
+        );
+        assert!(exact >= thresholds.confident);
+
+        // Accepted with low confidence: the same title by another artist
+        let cover = score(
+            &track,
+            &candidate("One More Time", "Cover Band", None, None),
+        );
+        assert!(cover >= thresholds.accept && cover < thresholds.confident);
+
+        // Rejected: the same title by another artist, with another duration
+        let other = score(
+            &timed,
+            &candidate("One More Time", "Cover Band", Some(200_000), None),
+        );
+        assert!(other < thresholds.accept);
+
+        // Rejected: another title by the same artist
+        let song = score(&track, &candidate("Digital Love", "Daft Punk", None, None));
+        assert!(song < thresholds.accept);
+    }
+
+    #[test]
+    fn search_queries_go_from_the_most_precise() {
+        let queries = search_queries(&track(
+            "Say \"Hello\" (Live)",
+            "The Band",
+            None,
+            Some("GBDUW0000059"),
+        ));
+
+        assert_eq!(
+            queries,
+            [
+                "isrc:GBDUW0000059",
+                "track:\"Say Hello (Live)\" artist:\"The Band\"",
+                "say hello the band",
+            ]
+        );
+        assert_eq!(
+            search_queries(&track("Teardrop", "Massive Attack", None, None)).len(),
+            2
+        );
+    }
+}
@@ -0,0 +1,34 @@
+use base64::{engine::general_purpose, Engine as _};
+use rand::{distributions::Alphanumeric, Rng};
+use sha2::{Digest, Sha256};
+
+/// Random string made of characters that don't need to be escaped in URLs
+pub fn random_string(len: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(len)
+        .map(char::from)
+        .collect()
+}
+
+/// Proof Key for Code Exchange (RFC 7636), lets a client without secret exchange its code safely
+#[derive(Debug, Clone)]
+pub struct Pkce {
+    /// Sent with the code when fetching the token
+    pub verifier: String,
+    /// Sent in the authorization URL
+    pub challenge: String,
+}
+
+impl Pkce {
+    pub fn new() -> Self {
+        let verifier = random_string(64);
+        let challenge =
+            general_purpose::URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()));
+
+        Self {
+            verifier,
+            challenge,
+        }
+    }
+}
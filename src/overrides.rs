@@ -0,0 +1,265 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::deezer::DeezerTrack;
+use crate::logger::{log, LogCategory};
+use crate::report::{Report, ReportEntry};
+use crate::spotify::SpotifyTrack;
+
+/// A manual match, looked up by Deezer id first, then ISRC, then title and artist
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Override {
+    /// Deezer id of the track
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deezer_id: Option<i64>,
+    /// ISRC of the track
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub isrc: Option<String>,
+    /// Title of the track, with `artist`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// Artist of the track, with `title`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub artist: Option<String>,
+    /// Spotify track to use, as a `spotify:track:` URI or an open.spotify.com link
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spotify: Option<String>,
+    /// Don't transfer this track at all
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub skip: bool,
+}
+
+/// What an override says to do with a track
+#[derive(Debug, Clone, PartialEq)]
+pub enum OverrideTarget {
+    /// Spotify track id
+    Track(String),
+    /// The track isn't transferred
+    Skip,
+}
+
+/// Manual matches, from the overrides file
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Overrides {
+    /// The manual matches, looked at in order
+    #[serde(default, rename = "override")]
+    pub entries: Vec<Override>,
+}
+
+/// Extracts the track id from a `spotify:track:<id>` URI, an open.spotify.com link or a bare id
+pub fn parse_track_id(input: &str) -> Option<String> {
+    let input = input.trim();
+    let id = if let Some(id) = input.strip_prefix("spotify:track:") {
+        id
+    } else if let Some((_, rest)) = input.split_once("open.spotify.com/") {
+        let rest = rest.split(['?', '#']).next().unwrap_or_default();
+        // Links can have a locale segment, e.g. /intl-fr/track/<id>
+        rest.rsplit_once("track/").map(|(_, id)| id)?
+    } else {
+        input
+    };
+
+    (id.len() == 22 && id.chars().all(|c| c.is_ascii_alphanumeric())).then(|| id.to_owned())
+}
+
+impl Override {
+    fn target(&self) -> Option<OverrideTarget> {
+        if self.skip {
+            return Some(OverrideTarget::Skip);
+        }
+
+        self.spotify
+            .as_deref()
+            .and_then(parse_track_id)
+            .map(OverrideTarget::Track)
+    }
+
+    fn same_key(&self, other: &Override) -> bool {
+        self.deezer_id == other.deezer_id
+            && self.isrc == other.isrc
+            && self.title == other.title
+            && self.artist == other.artist
+    }
+}
+
+impl Overrides {
+    /// Loads the overrides file, a missing file means no overrides
+    pub fn load(path: &Path) -> Result<Self, String> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let content = std::fs::read_to_string(path)
+            .map_err(|err| format!("Failed to read the overrides {}: {err}", path.display()))?;
+
+        let overrides: Self = toml::from_str(&content)
+            .map_err(|err| format!("Failed to parse the overrides {}: {err}", path.display()))?;
+
+        for (i, entry) in overrides.entries.iter().enumerate() {
+            let has_key = entry.deezer_id.is_some()
+                || entry.isrc.is_some()
+                || (entry.title.is_some() && entry.artist.is_some());
+
+            if !has_key || entry.target().is_none() || (entry.skip && entry.spotify.is_some()) {
+                return Err(format!(
+                    "Invalid override #{} in {}: it needs a deezer_id, an isrc or a title and artist, \
+                     and either a valid spotify track or skip = true",
+                    i + 1,
+                    path.display()
+                ));
+            }
+        }
+
+        Ok(overrides)
+    }
+
+    /// Writes the overrides to `path`
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let content = toml::to_string_pretty(self)
+            .map_err(|err| format!("Failed to serialize the overrides: {err}"))?;
+
+        std::fs::write(path, content)
+            .map_err(|err| format!("Failed to write the overrides to {}: {err}", path.display()))
+    }
+
+    /// Finds the override for this track, if any
+    pub fn get(&self, track: &DeezerTrack) -> Option<OverrideTarget> {
+        let by_id = self
+            .entries
+            .iter()
+            .find(|e| e.deezer_id.is_some_and(|id| id == track.id));
+        let by_isrc = || {
+            self.entries
+                .iter()
+                .find(|e| e.isrc.is_some() && e.isrc == track.isrc)
+        };
+        let by_name = || {
+            self.entries.iter().find(|e| {
+                e.title
+                    .as_deref()
+                    .is_some_and(|t| t.eq_ignore_ascii_case(&track.title))
+                    && e.artist
+                        .as_deref()
+                        .is_some_and(|a| a.eq_ignore_ascii_case(&track.artist_name))
+            })
+        };
+
+        by_id.or_else(by_isrc).or_else(by_name)?.target()
+    }
+
+    /// Adds the override, replacing an existing one for the same track
+    pub fn insert(&mut self, entry: Override) {
+        self.entries.retain(|e| !e.same_key(&entry));
+        self.entries.push(entry);
+    }
+
+    /// Adds overrides for the entries of an unmatched report, returns how many were added.
+    ///
+    /// `choose` decides what to do with each entry, `None` leaves it without override.
+    pub fn add_from_report(
+        &mut self,
+        report: &Report,
+        mut choose: impl FnMut(&ReportEntry) -> Result<Option<OverrideTarget>, String>,
+    ) -> Result<usize, String> {
+        let mut added = 0;
+
+        for entry in &report.entries {
+            let Some(target) = choose(entry)? else {
+                continue;
+            };
+
+            let mut new = Override {
+                deezer_id: (entry.deezer_id != 0).then_some(entry.deezer_id),
+                ..Default::default()
+            };
+
+            if new.deezer_id.is_none() {
+                new.isrc = entry.isrc.clone();
+            }
+            if new.deezer_id.is_none() && new.isrc.is_none() {
+                new.title = Some(entry.title.clone());
+                new.artist = Some(entry.artist_name.clone());
+            }
+
+            match target {
+                OverrideTarget::Track(id) => new.spotify = Some(format!("spotify:track:{id}")),
+                OverrideTarget::Skip => new.skip = true,
+            }
+
+            log!(
+                "Overrides",
+                LogCategory::Info,
+                "\"{}\" by \"{}\" -> {}",
+                entry.title,
+                entry.artist_name,
+                new.spotify.as_deref().unwrap_or("skip")
+            );
+
+            self.insert(new);
+            added += 1;
+        }
+
+        Ok(added)
+    }
+}
+
+/// Picks the best candidate of the entry, if any
+pub fn best_choice(entry: &ReportEntry) -> Result<Option<OverrideTarget>, String> {
+    Ok(choices(entry)
+        .first()
+        .map(|(track, _)| OverrideTarget::Track(track.id.clone())))
+}
+
+/// The Spotify tracks the user can pick from, the one used during the run first if any
+pub fn choices(entry: &ReportEntry) -> Vec<(&SpotifyTrack, f64)> {
+    entry
+        .matched
+        .iter()
+        .map(|track| (track, entry.confidence))
+        .chain(
+            entry
+                .candidates
+                .iter()
+                .map(|c| (&c.candidate.track, c.score)),
+        )
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_track_id;
+
+    #[test]
+    fn parse_track_id_accepts_uris_links_and_ids() {
+        let id = Some("0DiWol3AO6WpXZgp0goxAV".to_owned());
+
+        assert_eq!(parse_track_id("spotify:track:0DiWol3AO6WpXZgp0goxAV"), id);
+        assert_eq!(
+            parse_track_id("https://open.spotify.com/track/0DiWol3AO6WpXZgp0goxAV"),
+            id
+        );
+        assert_eq!(
+            parse_track_id("https://open.spotify.com/intl-fr/track/0DiWol3AO6WpXZgp0goxAV?si=abc"),
+            id
+        );
+        assert_eq!(
+            parse_track_id("open.spotify.com/track/0DiWol3AO6WpXZgp0goxAV#top"),
+            id
+        );
+        assert_eq!(parse_track_id(" 0DiWol3AO6WpXZgp0goxAV \n"), id);
+    }
+
+    #[test]
+    fn parse_track_id_rejects_anything_else() {
+        assert_eq!(parse_track_id(""), None);
+        assert_eq!(parse_track_id("spotify:album:2noRn2Aes5aoNVsU6iWThc"), None);
+        assert_eq!(
+            parse_track_id("https://open.spotify.com/album/2noRn2Aes5aoNVsU6iWThc"),
+            None
+        );
+        assert_eq!(parse_track_id("spotify:track:0DiWol3AO6WpXZgp0goxA"), None);
+        assert_eq!(parse_track_id("0DiWol3AO6WpXZgp0goxA-"), None);
+        assert_eq!(parse_track_id("https://www.deezer.com/track/3135556"), None);
+    }
+}
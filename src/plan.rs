@@ -0,0 +1,275 @@
+use std::{collections::HashSet, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::deezer::DeezerTrack;
+use crate::logger::{log, LogCategory};
+use crate::spotify::{Spotify, SpotifyPlaylist, SpotifyPlaylistInfo, SpotifyTrack};
+use crate::unix_time;
+
+const PLAN_VERSION: u32 = 2;
+
+/// Everything a transfer would do to a Spotify account, computed without writing anything
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Plan {
+    /// Format version of the plan file
+    pub version: u32,
+    /// Unix timestamp (seconds) of when the plan was made
+    pub created_at: u64,
+    /// Spotify account the plan was made for
+    pub user_id: String,
+    /// The plan replaces the playlists that already have the name, see [`PlaylistPlan::new`]
+    pub replace_existing: bool,
+    /// What is done to each playlist, in order
+    pub playlists: Vec<PlaylistPlan>,
+    /// Playlists of the account when the plan was started, the added playlists are compared to them
+    #[serde(skip)]
+    existing: Vec<SpotifyPlaylistInfo>,
+}
+
+/// What the plan does to a playlist
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PlaylistPlan {
+    /// Deezer id of the playlist
+    pub deezer_id: i64,
+    /// Name of the Spotify playlist
+    pub title: String,
+    /// Whether the playlist is created or replaced
+    pub action: PlaylistAction,
+    /// Tracks added to the playlist, in order
+    pub add: Vec<SpotifyTrack>,
+    /// Tracks removed from the playlist
+    pub remove: Vec<SpotifyTrack>,
+    /// Deezer tracks that weren't found on Spotify
+    pub unmatched: Vec<DeezerTrack>,
+}
+
+/// How a playlist is written to the Spotify account
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PlaylistAction {
+    /// A new playlist is created, even if one already has this title
+    Create,
+    /// The tracks of the playlist with this title are replaced, `snapshot_id` is its version at
+    /// planning time
+    Replace {
+        /// Spotify id of the replaced playlist
+        spotify_id: String,
+        /// Version of the playlist when the plan was made, `apply` refuses to run if it changed
+        snapshot_id: String,
+    },
+}
+
+impl PlaylistPlan {
+    /// What writing the matched playlist does to the Spotify account, for the plans and the
+    /// transfers alike: a new playlist is created, unless one of `existing` has its title. Then all
+    /// its tracks are removed and the matched ones added, in the Deezer order
+    pub async fn new(
+        spotify: &Spotify<'_>,
+        existing: &[SpotifyPlaylistInfo],
+        playlist: SpotifyPlaylist,
+    ) -> Result<Self, String> {
+        let (action, remove) = match existing.iter().find(|p| p.name == playlist.title) {
+            Some(current) => {
+                // Removing a track removes each of its occurrences
+                let mut seen = HashSet::new();
+                let remove = spotify
+                    .get_playlist_tracks(&current.id)
+                    .await?
+                    .into_iter()
+                    .filter(|t| seen.insert(t.id.clone()))
+                    .collect();
+                let action = PlaylistAction::Replace {
+                    spotify_id: current.id.clone(),
+                    snapshot_id: current.snapshot_id.clone(),
+                };
+
+                (action, remove)
+            }
+            None => (PlaylistAction::Create, Vec::new()),
+        };
+
+        Ok(Self {
+            deezer_id: playlist.deezer_id,
+            title: playlist.title,
+            action,
+            add: playlist.tracks,
+            remove,
+            unmatched: playlist.unmatched,
+        })
+    }
+}
+
+impl Plan {
+    /// Starts an empty plan for the logged in user
+    pub async fn new(spotify: &Spotify<'_>, replace_existing: bool) -> Result<Self, String> {
+        let existing = if replace_existing {
+            spotify.get_my_playlists().await?
+        } else {
+            Vec::new()
+        };
+
+        Ok(Self {
+            version: PLAN_VERSION,
+            created_at: unix_time().as_secs(),
+            user_id: spotify.get_my_id().await?,
+            replace_existing,
+            playlists: Vec::new(),
+            existing,
+        })
+    }
+
+    /// Compares the matched playlist with what's currently on the Spotify account and adds it
+    pub async fn add(
+        &mut self,
+        spotify: &Spotify<'_>,
+        playlist: SpotifyPlaylist,
+    ) -> Result<(), String> {
+        // Only listed with replace_existing
+        let planned = PlaylistPlan::new(spotify, &self.existing, playlist).await?;
+        self.playlists.push(planned);
+
+        Ok(())
+    }
+
+    /// Writes the plan to `path`
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|err| format!("Failed to serialize the plan: {err}"))?;
+
+        std::fs::write(path, json)
+            .map_err(|err| format!("Failed to write the plan to {}: {err}", path.display()))
+    }
+
+    /// Reads a plan written by [`Plan::save`], refusing the ones of another version
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let json = std::fs::read_to_string(path)
+            .map_err(|err| format!("Failed to read the plan from {}: {err}", path.display()))?;
+
+        let plan: Self = serde_json::from_str(&json)
+            .map_err(|err| format!("Failed to parse the plan {}: {err}", path.display()))?;
+
+        if plan.version != PLAN_VERSION {
+            return Err(format!(
+                "Unsupported plan version {} (expected {PLAN_VERSION}), please make a new plan",
+                plan.version
+            ));
+        }
+
+        Ok(plan)
+    }
+
+    /// Prints what the plan would do
+    pub fn summary(&self) {
+        for playlist in &self.playlists {
+            let action = match playlist.action {
+                PlaylistAction::Create => "create",
+                PlaylistAction::Replace { .. } => "replace",
+            };
+
+            log!(
+                "Plan",
+                LogCategory::Info,
+                "{action} \"{}\": +{} -{} ({} unmatched)",
+                playlist.title,
+                playlist.add.len(),
+                playlist.remove.len(),
+                playlist.unmatched.len()
+            );
+        }
+    }
+
+    /// Lists what changed on the Spotify account since the plan was made, empty if nothing did
+    async fn drift(&self, spotify: &Spotify<'_>) -> Result<Vec<String>, String> {
+        let user_id = spotify.get_my_id().await?;
+
+        if user_id != self.user_id {
+            return Ok(vec![format!(
+                "the plan was made for Spotify user \"{}\" but you're logged in as \"{user_id}\"",
+                self.user_id
+            )]);
+        }
+
+        let existing = spotify.get_my_playlists().await?;
+        let mut changes = Vec::new();
+
+        for playlist in &self.playlists {
+            match &playlist.action {
+                PlaylistAction::Create => {
+                    // Without replace_existing, a playlist with the title doesn't change anything
+                    if self.replace_existing && existing.iter().any(|p| p.name == playlist.title) {
+                        changes.push(format!("playlist \"{}\" was created", playlist.title));
+                    }
+                }
+                PlaylistAction::Replace {
+                    spotify_id,
+                    snapshot_id,
+                } => match existing.iter().find(|p| &p.id == spotify_id) {
+                    None => changes.push(format!("playlist \"{}\" was deleted", playlist.title)),
+                    Some(current) if &current.snapshot_id != snapshot_id => {
+                        changes.push(format!("playlist \"{}\" was modified", playlist.title))
+                    }
+                    Some(_) => {}
+                },
+            }
+        }
+
+        Ok(changes)
+    }
+
+    /// Executes the plan, refusing to do anything if the Spotify account changed since it was made
+    pub async fn apply(&self, spotify: &Spotify<'_>) -> Result<(), String> {
+        let changes = self.drift(spotify).await?;
+
+        if !changes.is_empty() {
+            return Err(format!(
+                "Your Spotify account changed since the plan was made, please make a new plan: {}",
+                changes.join(", ")
+            ));
+        }
+
+        for playlist in &self.playlists {
+            let add = playlist
+                .add
+                .iter()
+                .map(SpotifyTrack::uri)
+                .collect::<Vec<String>>();
+            let remove = playlist
+                .remove
+                .iter()
+                .map(SpotifyTrack::uri)
+                .collect::<Vec<String>>();
+
+            match &playlist.action {
+                PlaylistAction::Create => {
+                    let id = spotify
+                        .create_playlist(&self.user_id, &playlist.title)
+                        .await?;
+                    spotify.add_tracks(&id, &add).await?;
+
+                    log!(
+                        "Spotify",
+                        LogCategory::Success,
+                        { playlist: playlist.title },
+                        "Created playlist \"{}\"",
+                        playlist.title
+                    );
+                }
+                PlaylistAction::Replace { spotify_id, .. } => {
+                    spotify.remove_tracks(spotify_id, &remove).await?;
+                    spotify.add_tracks(spotify_id, &add).await?;
+
+                    log!(
+                        "Spotify",
+                        LogCategory::Success,
+                        { playlist: playlist.title },
+                        "Replaced playlist \"{}\"",
+                        playlist.title
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,931 @@
+use std::{
+    fmt::Write,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::deezer::{DeezerPlaylist, DeezerTrack};
+use crate::logger::{log, LogCategory};
+use crate::overrides::parse_track_id;
+use crate::unix_time;
+
+const ARCHIVE_VERSION: u32 = 1;
+
+// Normalized headers of the CSV columns: ours, then Exportify, TuneMyMusic and Soundiiz ones
+const TITLE: &[&str] = &["title", "trackname", "tracktitle", "songtitle", "name"];
+const ARTIST: &[&str] = &["artist", "artistnames", "artistname", "artists"];
+const ALBUM: &[&str] = &["album", "albumname", "albumtitle"];
+const ISRC: &[&str] = &["isrc"];
+const SPOTIFY: &[&str] = &[
+    "trackuri",
+    "spotifyuri",
+    "spotifyid",
+    "spotifytrackid",
+    "uri",
+];
+const DURATION: &[&str] = &["duration", "trackdurationms", "durationms"];
+const PLAYLIST: &[&str] = &["playlist", "playlistname", "playlisttitle"];
+
+/// File formats the playlists can be exported to and imported from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// Versioned archive with every field of the tracks
+    Json,
+    /// Comma, semicolon or tab separated values, with a header
+    Csv,
+    /// Extended M3U, UTF-8
+    M3u8,
+    /// XML Shareable Playlist Format
+    Xspf,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    /// `json`, `csv`, `m3u8` or `xspf`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "json" => Ok(Format::Json),
+            "csv" => Ok(Format::Csv),
+            "m3u8" => Ok(Format::M3u8),
+            "xspf" => Ok(Format::Xspf),
+            _ => Err(format!(
+                "Unknown format \"{s}\", expected json, csv, m3u8 or xspf"
+            )),
+        }
+    }
+}
+
+impl Format {
+    /// Extension of the files, without the dot
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Json => "json",
+            Format::Csv => "csv",
+            Format::M3u8 => "m3u8",
+            Format::Xspf => "xspf",
+        }
+    }
+
+    /// Guesses the format from the extension of the file
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+
+        match extension.as_str() {
+            "json" => Some(Format::Json),
+            "csv" => Some(Format::Csv),
+            "m3u8" | "m3u" => Some(Format::M3u8),
+            "xspf" => Some(Format::Xspf),
+            _ => None,
+        }
+    }
+
+    /// M3U8 and XSPF files hold a single playlist
+    pub fn can_bundle(self) -> bool {
+        matches!(self, Format::Json | Format::Csv)
+    }
+}
+
+/// Content of the JSON exports
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Archive {
+    /// Format version of the archive
+    pub version: u32,
+    /// Unix timestamp (seconds) of the export
+    pub exported_at: u64,
+    /// The exported playlists
+    pub playlists: Vec<DeezerPlaylist>,
+}
+
+/// A track of the CSV exports, the playlist columns repeat on each row
+#[derive(Serialize, Debug)]
+struct CsvRow {
+    playlist_id: i64,
+    playlist: String,
+    position: usize,
+    deezer_id: i64,
+    title: String,
+    artist: String,
+    album: Option<String>,
+    /// In seconds
+    duration: Option<u64>,
+    isrc: Option<String>,
+}
+
+/// Headers of the CSV columns, for the files whose headers aren't recognized. The unset ones are
+/// detected
+#[derive(Debug, Clone, Default)]
+pub struct ColumnMap {
+    /// Header of the playlist title column
+    pub playlist: Option<String>,
+    /// Header of the track title column
+    pub title: Option<String>,
+    /// Header of the artist column
+    pub artist: Option<String>,
+    /// Header of the album column
+    pub album: Option<String>,
+    /// Header of the ISRC column
+    pub isrc: Option<String>,
+    /// Spotify track URI, link or id
+    pub spotify: Option<String>,
+    /// In seconds, or milliseconds when the header ends with "ms"
+    pub duration: Option<String>,
+}
+
+impl FromStr for ColumnMap {
+    type Err = String;
+
+    /// `title=Song,artist=Performer`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut columns = ColumnMap::default();
+
+        for pair in s.split(',').filter(|pair| !pair.trim().is_empty()) {
+            let (field, header) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("Expected FIELD=COLUMN, got \"{pair}\""))?;
+            let slot = match field.trim() {
+                "playlist" => &mut columns.playlist,
+                "title" => &mut columns.title,
+                "artist" => &mut columns.artist,
+                "album" => &mut columns.album,
+                "isrc" => &mut columns.isrc,
+                "spotify" => &mut columns.spotify,
+                "duration" => &mut columns.duration,
+                field => {
+                    return Err(format!(
+                        "Unknown field \"{field}\", expected playlist, title, artist, album, isrc, spotify or duration"
+                    ))
+                }
+            };
+
+            *slot = Some(header.trim().to_owned());
+        }
+
+        Ok(columns)
+    }
+}
+
+/// `Track Name` and `track_name` both become `trackname`
+pub(crate) fn normalize_header(header: &str) -> String {
+    header
+        .chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+fn track_link(id: i64) -> String {
+    format!("https://www.deezer.com/track/{id}")
+}
+
+/// The id of a `deezer.com/track/<id>` link (with or without locale), 0 for anything else
+fn track_id(location: &str) -> i64 {
+    location
+        .split_once("deezer.com/")
+        .and_then(|(_, rest)| rest.rsplit_once("track/"))
+        .and_then(|(_, id)| id.split(['?', '#', '/']).next())
+        .and_then(|id| id.parse().ok())
+        .unwrap_or_default()
+}
+
+/// Splits `Artist - Title`, everything is the title without separator
+fn split_artist_title(s: &str) -> (String, String) {
+    match s.split_once(" - ") {
+        Some((artist, title)) => (artist.trim().to_owned(), title.trim().to_owned()),
+        None => (String::new(), s.trim().to_owned()),
+    }
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// `<title>-<id>` with only the characters that are safe in file names on every platform
+fn file_stem(playlist: &DeezerPlaylist) -> String {
+    let title = playlist
+        .title
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || matches!(c, ' ' | '-' | '_') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>();
+
+    format!("{}-{}", title.trim(), playlist.id)
+}
+
+fn to_json(playlists: &[&DeezerPlaylist]) -> Result<String, String> {
+    serde_json::to_string_pretty(&Archive {
+        version: ARCHIVE_VERSION,
+        exported_at: unix_time().as_secs(),
+        playlists: playlists.iter().map(|&p| p.clone()).collect(),
+    })
+    .map_err(|err| format!("Failed to serialize the playlists: {err}"))
+}
+
+fn to_csv(playlists: &[&DeezerPlaylist]) -> Result<String, String> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+
+    for playlist in playlists {
+        for (position, track) in playlist.tracks.iter().enumerate() {
+            writer
+                .serialize(CsvRow {
+                    playlist_id: playlist.id,
+                    playlist: playlist.title.clone(),
+                    position: position + 1,
+                    deezer_id: track.id,
+                    title: track.title.clone(),
+                    artist: track.artist_name.clone(),
+                    album: track.album_title.clone(),
+                    duration: track.duration,
+                    isrc: track.isrc.clone(),
+                })
+                .map_err(|err| format!("Failed to write the CSV: {err}"))?;
+        }
+    }
+
+    let bytes = writer
+        .into_inner()
+        .map_err(|err| format!("Failed to write the CSV: {err}"))?;
+
+    String::from_utf8(bytes).map_err(|err| format!("Failed to write the CSV: {err}"))
+}
+
+fn to_m3u8(playlist: &DeezerPlaylist) -> String {
+    let mut out = format!("#EXTM3U\n#PLAYLIST:{}\n", playlist.title);
+
+    for track in &playlist.tracks {
+        let duration = track.duration.map_or(-1, |d| d as i64);
+
+        let _ = writeln!(
+            out,
+            "#EXTINF:{duration},{} - {}",
+            track.artist_name, track.title
+        );
+        if let Some(album) = &track.album_title {
+            let _ = writeln!(out, "#EXTALB:{album}");
+        }
+        let _ = writeln!(out, "{}", track_link(track.id));
+    }
+
+    out
+}
+
+fn to_xspf(playlist: &DeezerPlaylist) -> String {
+    let mut out = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n",
+    );
+
+    let _ = writeln!(out, "  <title>{}</title>", escape_xml(&playlist.title));
+    let _ = writeln!(
+        out,
+        "  <identifier>https://www.deezer.com/playlist/{}</identifier>",
+        playlist.id
+    );
+    out.push_str("  <trackList>\n");
+
+    for (position, track) in playlist.tracks.iter().enumerate() {
+        out.push_str("    <track>\n");
+        let _ = writeln!(out, "      <location>{}</location>", track_link(track.id));
+        if let Some(isrc) = &track.isrc {
+            let _ = writeln!(
+                out,
+                "      <identifier>urn:isrc:{}</identifier>",
+                escape_xml(isrc)
+            );
+        }
+        let _ = writeln!(out, "      <title>{}</title>", escape_xml(&track.title));
+        let _ = writeln!(
+            out,
+            "      <creator>{}</creator>",
+            escape_xml(&track.artist_name)
+        );
+        if let Some(album) = &track.album_title {
+            let _ = writeln!(out, "      <album>{}</album>", escape_xml(album));
+        }
+        let _ = writeln!(out, "      <trackNum>{}</trackNum>", position + 1);
+        if let Some(duration) = track.duration {
+            let _ = writeln!(out, "      <duration>{}</duration>", duration * 1000);
+        }
+        out.push_str("    </track>\n");
+    }
+
+    out.push_str("  </trackList>\n</playlist>\n");
+
+    out
+}
+
+fn render(format: Format, playlists: &[&DeezerPlaylist]) -> Result<String, String> {
+    match (format, playlists) {
+        (Format::Json, _) => to_json(playlists),
+        (Format::Csv, _) => to_csv(playlists),
+        (Format::M3u8, [playlist]) => Ok(to_m3u8(playlist)),
+        (Format::Xspf, [playlist]) => Ok(to_xspf(playlist)),
+        _ => Err(format!(
+            "A {} file can only hold one playlist",
+            format.extension()
+        )),
+    }
+}
+
+/// Writes the playlists in `dir`, one file per playlist or a single `playlists.<ext>` with `bundle`.
+/// Returns the paths of the written files
+pub fn export(
+    playlists: &[DeezerPlaylist],
+    format: Format,
+    dir: &Path,
+    bundle: bool,
+) -> Result<Vec<PathBuf>, String> {
+    if bundle && !format.can_bundle() {
+        return Err(format!(
+            "A {} file can only hold one playlist, export one file per playlist instead",
+            format.extension()
+        ));
+    }
+
+    std::fs::create_dir_all(dir).map_err(|err| {
+        format!(
+            "Failed to create the export directory {}: {err}",
+            dir.display()
+        )
+    })?;
+
+    let files = if bundle {
+        vec![(
+            dir.join(format!("playlists.{}", format.extension())),
+            playlists.iter().collect::<Vec<_>>(),
+        )]
+    } else {
+        playlists
+            .iter()
+            .map(|playlist| {
+                (
+                    dir.join(format!("{}.{}", file_stem(playlist), format.extension())),
+                    vec![playlist],
+                )
+            })
+            .collect()
+    };
+
+    let mut written = Vec::new();
+
+    for (path, playlists) in files {
+        let content = render(format, &playlists)?;
+
+        std::fs::write(&path, content)
+            .map_err(|err| format!("Failed to write {}: {err}", path.display()))?;
+
+        written.push(path);
+    }
+
+    Ok(written)
+}
+
+fn from_json(content: &str) -> Result<Vec<DeezerPlaylist>, String> {
+    let archive: Archive =
+        serde_json::from_str(content).map_err(|err| format!("Failed to parse the JSON: {err}"))?;
+
+    if archive.version > ARCHIVE_VERSION {
+        return Err(format!(
+            "The archive was made by a newer version (format {}), please update",
+            archive.version
+        ));
+    }
+
+    Ok(archive.playlists)
+}
+
+/// Index of the first column whose normalized header is one of the aliases, in order of preference
+fn find_column(headers: &[String], aliases: &[&str]) -> Option<usize> {
+    aliases
+        .iter()
+        .find_map(|alias| headers.iter().position(|h| h == alias))
+}
+
+fn from_csv(
+    content: &str,
+    default_title: &str,
+    columns: &ColumnMap,
+) -> Result<Vec<DeezerPlaylist>, String> {
+    let content = content.trim_start_matches('\u{feff}');
+    // Spreadsheets in some locales save with semicolons
+    let header = content.lines().next().unwrap_or_default();
+    let delimiter = [b',', b';', b'\t']
+        .into_iter()
+        .max_by_key(|&d| header.bytes().filter(|&b| b == d).count())
+        .unwrap_or(b',');
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .flexible(true)
+        .from_reader(content.as_bytes());
+
+    let names = reader
+        .headers()
+        .map_err(|err| format!("Invalid CSV header: {err}"))?
+        .iter()
+        .map(|name| name.trim().to_owned())
+        .collect::<Vec<_>>();
+    let headers = names
+        .iter()
+        .map(|name| normalize_header(name))
+        .collect::<Vec<_>>();
+    let column = |field: &str, mapped: &Option<String>, aliases: &[&str]| match mapped {
+        Some(name) => headers
+            .iter()
+            .position(|h| *h == normalize_header(name))
+            .map(Some)
+            .ok_or_else(|| format!("No column \"{name}\" for the {field} in the CSV")),
+        None => Ok(find_column(&headers, aliases)),
+    };
+    let required = |field: &str, index: Option<usize>| {
+        index.ok_or_else(|| {
+            format!(
+                "Can't find the {field} column among {}, map it with --columns",
+                names.join(", ")
+            )
+        })
+    };
+
+    let title = required("title", column("title", &columns.title, TITLE)?)?;
+    let artist = required("artist", column("artist", &columns.artist, ARTIST)?)?;
+    let album = column("album", &columns.album, ALBUM)?;
+    let isrc = column("ISRC", &columns.isrc, ISRC)?;
+    let spotify = column("Spotify track", &columns.spotify, SPOTIFY)?;
+    let duration = column("duration", &columns.duration, DURATION)?;
+    let playlist = column("playlist", &columns.playlist, PLAYLIST)?;
+    let playlist_id = find_column(&headers, &["playlistid"]);
+    let deezer_id = find_column(&headers, &["deezerid"]);
+    // Exportify and TuneMyMusic give milliseconds, in a column named so
+    let duration_ms = duration.is_some_and(|d| headers[d].ends_with("ms"));
+
+    let mut playlists: Vec<DeezerPlaylist> = Vec::new();
+
+    for (i, row) in reader.records().enumerate() {
+        let row = row.map_err(|err| format!("Invalid CSV row {}: {err}", i + 1))?;
+        let cell = |index: Option<usize>| {
+            index
+                .and_then(|index| row.get(index))
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+        };
+
+        let playlist_id = cell(playlist_id)
+            .and_then(|id| id.parse().ok())
+            .unwrap_or_default();
+        let playlist_title = cell(playlist).unwrap_or(default_title);
+        let track = DeezerTrack {
+            id: cell(deezer_id)
+                .and_then(|id| id.parse().ok())
+                .unwrap_or_default(),
+            title: cell(Some(title)).unwrap_or_default().to_owned(),
+            artist_name: cell(Some(artist)).unwrap_or_default().to_owned(),
+            album_title: cell(album).map(str::to_owned),
+            duration: cell(duration)
+                .and_then(|d| d.parse::<f64>().ok())
+                .map(|d| if duration_ms { d / 1000.0 } else { d })
+                .filter(|&d| d > 0.0)
+                .map(|d| d.round() as u64),
+            isrc: cell(isrc).map(str::to_owned),
+            spotify_id: cell(spotify).and_then(parse_track_id),
+        };
+
+        // Rows stay in file order, so tracks can be reordered by moving the rows
+        match playlists
+            .iter_mut()
+            .find(|p| p.id == playlist_id && p.title == playlist_title)
+        {
+            Some(playlist) => playlist.tracks.push(track),
+            None => playlists.push(DeezerPlaylist {
+                id: playlist_id,
+                title: playlist_title.to_owned(),
+                tracks: vec![track],
+            }),
+        }
+    }
+
+    Ok(playlists)
+}
+
+fn from_m3u8(content: &str, default_title: &str) -> DeezerPlaylist {
+    let mut playlist = DeezerPlaylist {
+        id: 0,
+        title: default_title.to_owned(),
+        tracks: Vec::new(),
+    };
+    // Set by #EXTINF and #EXTALB, used by the location line that follows
+    let mut info: Option<(Option<u64>, String, String)> = None;
+    let mut album = None;
+
+    for line in content.lines().map(str::trim) {
+        if let Some(title) = line.strip_prefix("#PLAYLIST:") {
+            playlist.title = title.trim().to_owned();
+        } else if let Some(extinf) = line.strip_prefix("#EXTINF:") {
+            let (duration, name) = extinf.split_once(',').unwrap_or((extinf, ""));
+            let duration = duration
+                .split_whitespace()
+                .next()
+                .and_then(|d| d.parse::<i64>().ok())
+                .filter(|&d| d > 0)
+                .map(|d| d as u64);
+            let (artist, title) = split_artist_title(name);
+
+            info = Some((duration, artist, title));
+        } else if let Some(name) = line.strip_prefix("#EXTALB:") {
+            album = Some(name.trim().to_owned());
+        } else if !line.is_empty() && !line.starts_with('#') {
+            // Without a name in #EXTINF, local files are often named `Artist - Title.mp3`
+            let (duration, artist_name, title) = match info.take() {
+                Some((duration, artist, title)) if !title.is_empty() => (duration, artist, title),
+                info => {
+                    let stem = Path::new(line)
+                        .file_stem()
+                        .and_then(|stem| stem.to_str())
+                        .unwrap_or(line);
+                    let (artist, title) = split_artist_title(stem);
+
+                    (info.and_then(|(duration, ..)| duration), artist, title)
+                }
+            };
+
+            playlist.tracks.push(DeezerTrack {
+                id: track_id(line),
+                title,
+                artist_name,
+                album_title: album.take(),
+                duration,
+                isrc: None,
+                spotify_id: None,
+            });
+        }
+    }
+
+    playlist
+}
+
+fn from_xspf(content: &str, default_title: &str) -> Result<DeezerPlaylist, String> {
+    let document = roxmltree::Document::parse(content)
+        .map_err(|err| format!("Failed to parse the XSPF: {err}"))?;
+    let root = document.root_element();
+    // XSPF elements are namespaced, only their local names matter
+    let child = |node: roxmltree::Node, name: &str| {
+        node.children()
+            .find(|c| c.tag_name().name() == name)
+            .and_then(|c| c.text())
+            .map(|text| text.trim().to_owned())
+    };
+
+    let mut playlist = DeezerPlaylist {
+        id: child(root, "identifier")
+            .and_then(|id| id.rsplit_once("playlist/")?.1.parse().ok())
+            .unwrap_or_default(),
+        title: child(root, "title").unwrap_or_else(|| default_title.to_owned()),
+        tracks: Vec::new(),
+    };
+
+    let tracks = root
+        .children()
+        .find(|c| c.tag_name().name() == "trackList")
+        .into_iter()
+        .flat_map(|list| list.children())
+        .filter(|c| c.tag_name().name() == "track");
+
+    for track in tracks {
+        playlist.tracks.push(DeezerTrack {
+            id: child(track, "location")
+                .map(|location| track_id(&location))
+                .unwrap_or_default(),
+            title: child(track, "title").unwrap_or_default(),
+            artist_name: child(track, "creator").unwrap_or_default(),
+            album_title: child(track, "album"),
+            duration: child(track, "duration")
+                .and_then(|ms| ms.parse::<u64>().ok())
+                .map(|ms| ms / 1000),
+            isrc: track
+                .children()
+                .filter(|c| c.tag_name().name() == "identifier")
+                .filter_map(|c| {
+                    c.text()?
+                        .trim()
+                        .strip_prefix("urn:isrc:")
+                        .map(str::to_owned)
+                })
+                .next(),
+            spotify_id: None,
+        });
+    }
+
+    Ok(playlist)
+}
+
+/// Reads the playlists of a file written by [`export`], by another tool or by hand. Without
+/// `format`, it's guessed from the extension. The columns of CSV files are recognized by their
+/// headers, `columns` sets the ones that aren't
+pub fn import(
+    path: &Path,
+    format: Option<Format>,
+    columns: &ColumnMap,
+) -> Result<Vec<DeezerPlaylist>, String> {
+    let format = format.or_else(|| Format::from_path(path)).ok_or_else(|| {
+        format!(
+            "Can't guess the format of {} from its extension, set it explicitly",
+            path.display()
+        )
+    })?;
+    let content = std::fs::read_to_string(path)
+        .map_err(|err| format!("Failed to read {}: {err}", path.display()))?;
+    // CSV, M3U8 and XSPF files may have no title, the file name is used then
+    let default_title = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or_default();
+
+    let mut playlists = match format {
+        Format::Json => from_json(&content),
+        Format::Csv => from_csv(&content, default_title, columns),
+        Format::M3u8 => Ok(vec![from_m3u8(&content, default_title)]),
+        Format::Xspf => from_xspf(&content, default_title).map(|playlist| vec![playlist]),
+    }
+    .map_err(|err| format!("{err} in {}", path.display()))?;
+
+    // They can't be searched, the rest of the playlist still can
+    for playlist in &mut playlists {
+        let count = playlist.tracks.len();
+        playlist.tracks.retain(|track| !track.title.is_empty());
+
+        if playlist.tracks.len() < count {
+            log!(
+                "Import",
+                LogCategory::Warn,
+                { playlist: playlist.title },
+                "Skipping {} track(s) without title of \"{}\" in {}",
+                count - playlist.tracks.len(),
+                playlist.title,
+                path.display()
+            );
+        }
+    }
+
+    Ok(playlists)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{from_csv, from_m3u8, from_xspf, import, ColumnMap, Format};
+
+    #[test]
+    fn m3u8_reads_the_extinf_lines() {
+        let playlist = from_m3u8(
+            "#EXTM3U\n\
+             #PLAYLIST:Road trip\n\
+             #EXTINF:215,Daft Punk - One More Time\n\
+             #EXTALB:Discovery\n\
+             https://www.deezer.com/fr/track/3135556\n\
+             \n\
+             #EXTINF:-1 tvg-id=\"x\",Untitled\n\
+             /music/untitled.mp3\n",
+            "file",
+        );
+
+        assert_eq!(playlist.title, "Road trip");
+        assert_eq!(playlist.tracks.len(), 2);
+
+        let track = &playlist.tracks[0];
+        assert_eq!(track.id, 3135556);
+        assert_eq!(track.artist_name, "Daft Punk");
+        assert_eq!(track.title, "One More Time");
+        assert_eq!(track.album_title.as_deref(), Some("Discovery"));
+        assert_eq!(track.duration, Some(215));
+
+        let track = &playlist.tracks[1];
+        assert_eq!(track.id, 0);
+        assert_eq!(track.artist_name, "");
+        assert_eq!(track.title, "Untitled");
+        assert_eq!(track.album_title, None);
+        assert_eq!(track.duration, None);
+    }
+
+    #[test]
+    fn m3u8_names_the_tracks_after_the_files_without_extinf() {
+        let playlist = from_m3u8("/music/Air - La femme d'argent.flac\nsong.mp3\n", "file");
+
+        assert_eq!(playlist.title, "file");
+        assert_eq!(playlist.tracks[0].artist_name, "Air");
+        assert_eq!(playlist.tracks[0].title, "La femme d'argent");
+        assert_eq!(playlist.tracks[1].artist_name, "");
+        assert_eq!(playlist.tracks[1].title, "song");
+    }
+
+    #[test]
+    fn m3u8_names_the_tracks_after_the_files_without_extinf_name() {
+        let playlist = from_m3u8(
+            "#EXTM3U\n#EXTINF:301,\n/music/Daft Punk - Digital Love.mp3\n",
+            "file",
+        );
+
+        assert_eq!(playlist.tracks[0].artist_name, "Daft Punk");
+        assert_eq!(playlist.tracks[0].title, "Digital Love");
+        assert_eq!(playlist.tracks[0].duration, Some(301));
+    }
+
+    #[test]
+    fn import_skips_the_tracks_without_title() {
+        let path = std::env::temp_dir().join(format!("dts-import-{}.csv", std::process::id()));
+        std::fs::write(
+            &path,
+            "title,artist\nOne More Time,Daft Punk\n,Daft Punk\nDigital Love,Daft Punk\n",
+        )
+        .unwrap();
+
+        let playlists = import(&path, Some(Format::Csv), &ColumnMap::default());
+        std::fs::remove_file(&path).unwrap();
+
+        let titles = playlists.unwrap()[0]
+            .tracks
+            .iter()
+            .map(|track| track.title.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(titles, ["One More Time", "Digital Love"]);
+    }
+
+    #[test]
+    fn xspf_reads_the_namespaced_tracks() {
+        let playlist = from_xspf(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<playlist version="1" xmlns="http://xspf.org/ns/0/">
+  <title>Rock &amp; Roll</title>
+  <identifier>https://www.deezer.com/playlist/908622995</identifier>
+  <trackList>
+    <track>
+      <location>https://www.deezer.com/track/3135556</location>
+      <identifier>https://example.com/other</identifier>
+      <identifier>urn:isrc:GBDUW0000059</identifier>
+      <title> One More Time </title>
+      <creator>Daft Punk</creator>
+      <album>Discovery</album>
+      <duration>320357</duration>
+    </track>
+    <track>
+      <title>Untitled</title>
+    </track>
+  </trackList>
+</playlist>"#,
+            "file",
+        )
+        .unwrap();
+
+        assert_eq!(playlist.id, 908622995);
+        assert_eq!(playlist.title, "Rock & Roll");
+        assert_eq!(playlist.tracks.len(), 2);
+
+        let track = &playlist.tracks[0];
+        assert_eq!(track.id, 3135556);
+        assert_eq!(track.title, "One More Time");
+        assert_eq!(track.artist_name, "Daft Punk");
+        assert_eq!(track.album_title.as_deref(), Some("Discovery"));
+        assert_eq!(track.duration, Some(320));
+        assert_eq!(track.isrc.as_deref(), Some("GBDUW0000059"));
+
+        let track = &playlist.tracks[1];
+        assert_eq!(track.id, 0);
+        assert_eq!(track.artist_name, "");
+        assert_eq!(track.isrc, None);
+    }
+
+    #[test]
+    fn xspf_falls_back_to_the_file_name_and_rejects_broken_xml() {
+        let playlist = from_xspf(
+            r#"<playlist xmlns="http://xspf.org/ns/0/"><trackList/></playlist>"#,
+            "file",
+        )
+        .unwrap();
+
+        assert_eq!(playlist.id, 0);
+        assert_eq!(playlist.title, "file");
+        assert!(playlist.tracks.is_empty());
+        assert!(from_xspf("<playlist><title>", "file").is_err());
+    }
+
+    #[test]
+    fn csv_detects_the_exportify_columns() {
+        let playlists = from_csv(
+            "\u{feff}Track URI,Track Name,Artist Name(s),Album Name,Track Duration (ms),ISRC\n\
+             spotify:track:0DiWol3AO6WpXZgp0goxAV,One More Time,Daft Punk,Discovery,320357,GBDUW0000059\n\
+             ,Digital Love,Daft Punk,,,\n",
+            "liked",
+            &ColumnMap::default(),
+        )
+        .unwrap();
+
+        assert_eq!(playlists.len(), 1);
+        assert_eq!(playlists[0].title, "liked");
+
+        let track = &playlists[0].tracks[0];
+        assert_eq!(track.title, "One More Time");
+        assert_eq!(track.artist_name, "Daft Punk");
+        assert_eq!(track.album_title.as_deref(), Some("Discovery"));
+        assert_eq!(track.duration, Some(320));
+        assert_eq!(track.isrc.as_deref(), Some("GBDUW0000059"));
+        assert_eq!(track.spotify_id.as_deref(), Some("0DiWol3AO6WpXZgp0goxAV"));
+
+        let track = &playlists[0].tracks[1];
+        assert_eq!(track.album_title, None);
+        assert_eq!(track.duration, None);
+        assert_eq!(track.spotify_id, None);
+    }
+
+    #[test]
+    fn csv_groups_the_rows_by_playlist_with_any_delimiter() {
+        let content = "Playlist name;Title;Artist;Duration\n\
+                       Morning;One More Time;Daft Punk;320\n\
+                       Evening;Teardrop;Massive Attack;330\n\
+                       Morning;Digital Love;Daft Punk;301\n";
+
+        for content in [content.to_owned(), content.replace(';', "\t")] {
+            let playlists = from_csv(&content, "file", &ColumnMap::default()).unwrap();
+            let titles = |i: usize| {
+                playlists[i]
+                    .tracks
+                    .iter()
+                    .map(|track| track.title.as_str())
+                    .collect::<Vec<_>>()
+            };
+
+            assert_eq!(playlists.len(), 2);
+            assert_eq!(playlists[0].title, "Morning");
+            assert_eq!(titles(0), ["One More Time", "Digital Love"]);
+            assert_eq!(playlists[1].title, "Evening");
+            assert_eq!(titles(1), ["Teardrop"]);
+            // Not in milliseconds without "ms" in the header
+            assert_eq!(playlists[0].tracks[0].duration, Some(320));
+        }
+    }
+
+    #[test]
+    fn csv_reads_our_exports_back() {
+        let playlists = from_csv(
+            "playlist_id,playlist,position,deezer_id,title,artist,album,duration,isrc\n\
+             908622995,Road trip,1,3135556,One More Time,Daft Punk,Discovery,320,GBDUW0000059\n",
+            "file",
+            &ColumnMap::default(),
+        )
+        .unwrap();
+
+        assert_eq!(playlists[0].id, 908622995);
+        assert_eq!(playlists[0].title, "Road trip");
+        assert_eq!(playlists[0].tracks[0].id, 3135556);
+        assert_eq!(playlists[0].tracks[0].duration, Some(320));
+    }
+
+    #[test]
+    fn csv_uses_the_mapped_columns() {
+        let content = "Song,Performer,Name\nOne More Time,Daft Punk,ignored\n";
+
+        let err = from_csv(content, "file", &ColumnMap::default()).unwrap_err();
+        assert_eq!(
+            err,
+            "Can't find the artist column among Song, Performer, Name, map it with --columns"
+        );
+
+        let columns = "title=Song, artist=performer".parse::<ColumnMap>().unwrap();
+        let playlists = from_csv(content, "file", &columns).unwrap();
+        assert_eq!(playlists[0].tracks[0].title, "One More Time");
+        assert_eq!(playlists[0].tracks[0].artist_name, "Daft Punk");
+
+        let columns = "artist=Performer,album=Record"
+            .parse::<ColumnMap>()
+            .unwrap();
+        let err = from_csv(content, "file", &columns).unwrap_err();
+        assert_eq!(err, "No column \"Record\" for the album in the CSV");
+    }
+
+    #[test]
+    fn column_map_parses_the_pairs() {
+        let columns = " title = Song ,artist=Performer,,duration=Length (ms)"
+            .parse::<ColumnMap>()
+            .unwrap();
+
+        assert_eq!(columns.title.as_deref(), Some("Song"));
+        assert_eq!(columns.artist.as_deref(), Some("Performer"));
+        assert_eq!(columns.duration.as_deref(), Some("Length (ms)"));
+        assert_eq!(columns.album, None);
+        assert!("".parse::<ColumnMap>().unwrap().title.is_none());
+
+        assert_eq!(
+            "title".parse::<ColumnMap>().unwrap_err(),
+            "Expected FIELD=COLUMN, got \"title\""
+        );
+        assert!("genre=Style"
+            .parse::<ColumnMap>()
+            .unwrap_err()
+            .starts_with("Unknown field \"genre\""));
+    }
+}
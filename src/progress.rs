@@ -0,0 +1,52 @@
+use std::sync::OnceLock;
+
+/// Steps of a transfer, for progress displays
+#[derive(Debug, Clone, Copy)]
+pub enum Progress<'a> {
+    /// A transfer started
+    Started {
+        /// How many playlists are transferred
+        playlists: usize,
+    },
+    /// The tracks of the playlist are about to be matched
+    Playlist {
+        /// Title of the Deezer playlist
+        title: &'a str,
+    },
+    /// Tracks of the current playlist are searched
+    Searching {
+        /// How many
+        tracks: usize,
+    },
+    /// A track of the current playlist was searched
+    Searched {
+        /// True if it has a match
+        found: bool,
+    },
+    /// The playlist is transferred, or failed with `error`
+    Finished {
+        /// Title of the Deezer playlist
+        title: &'a str,
+        /// Tracks found on Spotify
+        matched: usize,
+        /// Tracks not found on Spotify
+        unmatched: usize,
+        /// Why the playlist failed, `None` if it was transferred
+        error: Option<&'a str>,
+    },
+}
+
+type Sink = Box<dyn Fn(Progress) + Send + Sync>;
+
+static SINK: OnceLock<Sink> = OnceLock::new();
+
+/// Sets where the progress goes, it's dropped until it's called. Only the first call counts
+pub fn set_sink(sink: impl Fn(Progress) + Send + Sync + 'static) {
+    let _ = SINK.set(Box::new(sink));
+}
+
+pub(crate) fn emit(progress: Progress) {
+    if let Some(sink) = SINK.get() {
+        sink(progress);
+    }
+}
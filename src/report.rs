@@ -0,0 +1,196 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::deezer::DeezerTrack;
+use crate::matcher::{MatchResult, MatchSource, ScoredCandidate};
+use crate::spotify::SpotifyTrack;
+use crate::unix_time;
+
+/// Why a track needs attention
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportStatus {
+    /// Not found on Spotify
+    Unmatched,
+    /// Found, with a score below the confident threshold
+    LowConfidence,
+}
+
+/// A Deezer track that couldn't be matched, or was matched with a low score
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReportEntry {
+    /// Title of the Deezer playlist
+    pub playlist: String,
+    /// Why the track needs attention
+    pub status: ReportStatus,
+    /// Deezer id of the track
+    pub deezer_id: i64,
+    /// Title of the track
+    pub title: String,
+    /// Name of the main artist
+    pub artist_name: String,
+    /// ISRC of the track, when the source has it
+    pub isrc: Option<String>,
+    /// Score of the match, 0 if there's none
+    pub confidence: f64,
+    /// The accepted match
+    pub matched: Option<SpotifyTrack>,
+    /// The searches that were sent to Spotify
+    pub queries: Vec<String>,
+    /// The best rejected candidates, to pick a manual match from
+    pub candidates: Vec<ScoredCandidate>,
+}
+
+/// Flat version of [`ReportEntry`] for the CSV report
+#[derive(Serialize)]
+struct CsvRow<'a> {
+    playlist: &'a str,
+    status: ReportStatus,
+    deezer_id: i64,
+    title: &'a str,
+    artist_name: &'a str,
+    isrc: &'a str,
+    confidence: String,
+    matched_uri: String,
+    queries: String,
+    candidates: String,
+}
+
+/// Tracks that need attention after a run
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Report {
+    /// Unix timestamp (seconds) of when the run started
+    pub created_at: u64,
+    /// The tracks that need attention, in the order they were matched
+    pub entries: Vec<ReportEntry>,
+}
+
+impl Report {
+    /// An empty report
+    pub fn new() -> Self {
+        Self {
+            created_at: unix_time().as_secs(),
+            entries: Vec::new(),
+        }
+    }
+
+    /// Keeps the track in the report if it's unmatched or low confidence
+    pub fn record(
+        &mut self,
+        playlist: &str,
+        track: &DeezerTrack,
+        result: &MatchResult,
+        low_confidence: bool,
+    ) {
+        if result.source == MatchSource::Skipped {
+            return;
+        }
+
+        let status = match (&result.track, low_confidence) {
+            (None, _) => ReportStatus::Unmatched,
+            (Some(_), true) => ReportStatus::LowConfidence,
+            (Some(_), false) => return,
+        };
+
+        self.entries.push(ReportEntry {
+            playlist: playlist.to_owned(),
+            status,
+            deezer_id: track.id,
+            title: track.title.clone(),
+            artist_name: track.artist_name.clone(),
+            isrc: track.isrc.clone(),
+            confidence: result.confidence,
+            matched: result.track.clone(),
+            queries: result.queries.clone(),
+            candidates: result.rejected.clone(),
+        });
+    }
+
+    /// True if no track needs attention
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Reads a report written by [`Report::write`]
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let json = std::fs::read_to_string(path)
+            .map_err(|err| format!("Failed to read the report {}: {err}", path.display()))?;
+
+        serde_json::from_str(&json)
+            .map_err(|err| format!("Failed to parse the report {}: {err}", path.display()))
+    }
+
+    /// Writes `unmatched-<timestamp>.json` and `.csv` in `dir`, returns both paths
+    pub fn write(&self, dir: &Path) -> Result<(PathBuf, PathBuf), String> {
+        std::fs::create_dir_all(dir).map_err(|err| {
+            format!(
+                "Failed to create the report directory {}: {err}",
+                dir.display()
+            )
+        })?;
+
+        let json_path = dir.join(format!("unmatched-{}.json", self.created_at));
+        let csv_path = dir.join(format!("unmatched-{}.csv", self.created_at));
+
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|err| format!("Failed to serialize the report: {err}"))?;
+        std::fs::write(&json_path, json).map_err(|err| {
+            format!(
+                "Failed to write the report to {}: {err}",
+                json_path.display()
+            )
+        })?;
+
+        let mut writer = csv::Writer::from_path(&csv_path).map_err(|err| {
+            format!(
+                "Failed to write the report to {}: {err}",
+                csv_path.display()
+            )
+        })?;
+
+        for entry in &self.entries {
+            let candidates = entry
+                .candidates
+                .iter()
+                .map(|c| {
+                    format!(
+                        "{} - {} ({}, {:.2})",
+                        c.candidate.track.title,
+                        c.candidate.track.artist_name,
+                        c.candidate.track.uri(),
+                        c.score
+                    )
+                })
+                .collect::<Vec<_>>();
+
+            writer
+                .serialize(CsvRow {
+                    playlist: &entry.playlist,
+                    status: entry.status,
+                    deezer_id: entry.deezer_id,
+                    title: &entry.title,
+                    artist_name: &entry.artist_name,
+                    isrc: entry.isrc.as_deref().unwrap_or_default(),
+                    confidence: format!("{:.2}", entry.confidence),
+                    matched_uri: entry
+                        .matched
+                        .as_ref()
+                        .map(SpotifyTrack::uri)
+                        .unwrap_or_default(),
+                    queries: entry.queries.join(" | "),
+                    candidates: candidates.join(" | "),
+                })
+                .map_err(|err| format!("Failed to write a report row: {err}"))?;
+        }
+
+        writer.flush().map_err(|err| {
+            format!(
+                "Failed to write the report to {}: {err}",
+                csv_path.display()
+            )
+        })?;
+
+        Ok((json_path, csv_path))
+    }
+}
@@ -1,79 +1,809 @@
-use std::sync::Arc;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
+use percent_encoding::percent_decode_str;
+use serde::Deserialize;
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
-    net::TcpListener,
-    sync::{Mutex, RwLock},
+    net::{TcpListener, TcpStream},
+    sync::{oneshot, OnceCell},
+    time::timeout,
 };
 
-use crate::deezer::CODE as DEEZER_CODE;
-use crate::spotify::CODE as SPOTIFY_CODE;
+use crate::logger::{log, LogCategory};
+use crate::oauth::random_string;
+
+/// Browsers send small requests, anything bigger than this isn't a callback
+const MAX_REQUEST_SIZE: usize = 16 * 1024;
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+const LOGIN_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// Shows a line the user has to act on (a login link, a prompt), whatever the log level
+pub type Prompt = Arc<dyn Fn(&str) + Send + Sync>;
+
+/// The authorization code, or the error sent back by the provider
+type CallbackResult = Result<String, String>;
+
+struct PendingLogin {
+    provider: &'static str,
+    sender: oneshot::Sender<CallbackResult>,
+}
+
+/// Logins waiting for their callback, by state
+type Pending = Arc<Mutex<HashMap<String, PendingLogin>>>;
+
+/// Where the callback server listens, the redirect URIs of both providers are made from it
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct CallbackConfig {
+    /// Spotify only accepts loopback IPs (`127.0.0.1` or `[::1]`), not localhost
+    pub host: String,
+    /// 0 picks a free one. Spotify accepts any port on a loopback IP,
+    /// Deezer needs the redirect URI registered for your app so it needs a fixed port
+    pub port: u16,
+    /// The callbacks are served on `<path>/Spotify` and `<path>/Deezer`
+    pub path: String,
+    /// Open the login pages in the browser
+    pub open_browser: bool,
+    /// Don't listen, the redirect URLs are pasted in the terminal
+    pub headless: bool,
+}
+
+impl Default for CallbackConfig {
+    fn default() -> Self {
+        Self {
+            host: String::from("127.0.0.1"),
+            port: 8080,
+            path: String::new(),
+            open_browser: true,
+            headless: false,
+        }
+    }
+}
+
+impl CallbackConfig {
+    /// `http://host:port/path`, without trailing slash
+    fn base_uri(&self, port: u16) -> String {
+        let path = self.path.trim_matches('/');
+
+        if path.is_empty() {
+            format!("http://{}:{port}", self.host)
+        } else {
+            format!("http://{}:{port}/{path}", self.host)
+        }
+    }
+}
+
+/// Callback server, receiving the redirections of the OAuth logins
+#[derive(Clone)]
+pub struct Server {
+    pending: Pending,
+    config: CallbackConfig,
+    /// `http://host:port/path`, without trailing slash, set once the server listens
+    base_uri: Arc<OnceCell<String>>,
+    /// Nothing listens, the redirect URLs are pasted in the terminal
+    headless: bool,
+    open_browser: bool,
+    prompt: Prompt,
+}
+
+/// A login waiting for its callback, dropping it cancels the login
+pub struct Login {
+    /// Random value to send in the authorization URL, the callback has to send it back to be accepted
+    pub state: String,
+    /// The URI the provider has to redirect to, it has to be registered in its app settings
+    pub redirect_uri: String,
+    provider: &'static str,
+    receiver: oneshot::Receiver<CallbackResult>,
+    pending: Pending,
+    headless: bool,
+    prompt: Prompt,
+}
+
+/// What the user pasted in headless mode
+fn parse_pasted(input: &str, state: &str) -> Result<CallbackResult, String> {
+    let input = input.trim();
+
+    if input.is_empty() {
+        return Err(String::from("Nothing was pasted"));
+    }
+
+    // A bare code, there's no state to check but the user copied it themselves
+    if !input.contains('?') && !input.contains('=') {
+        return Ok(Ok(input.to_owned()));
+    }
+
+    let query = input.split_once('?').map_or(input, |(_, query)| query);
+    let query = query.split('#').next().unwrap_or_default();
+    let params = form_urlencoded::parse(query.as_bytes())
+        .into_owned()
+        .collect::<HashMap<_, _>>();
+
+    if let Some(err) = params.get("error").or(params.get("error_reason")) {
+        return Ok(Err(err.to_owned()));
+    }
+
+    if params.get("state").map(String::as_str) != Some(state) {
+        return Err(String::from(
+            "This URL doesn't belong to this login, please use the link printed above",
+        ));
+    }
+
+    match params.get("code").filter(|code| !code.is_empty()) {
+        Some(code) => Ok(Ok(code.to_owned())),
+        None => Err(String::from("There's no code in this URL")),
+    }
+}
+
+/// Asks for the redirect URL (or the code) until something valid is pasted
+async fn read_pasted(
+    provider: &'static str,
+    state: &str,
+    prompt: &Prompt,
+) -> Result<CallbackResult, String> {
+    loop {
+        prompt(&format!(
+            "Paste the URL {provider} redirected you to (or just the code):"
+        ));
+
+        let line = tokio::task::spawn_blocking(|| {
+            let mut line = String::new();
+            std::io::stdin().read_line(&mut line).map(|_| line)
+        })
+        .await
+        .map_err(|err| err.to_string())
+        .and_then(|line| line.map_err(|err| err.to_string()));
+
+        match line {
+            Ok(line) if line.is_empty() => return Err(String::from("stdin was closed")),
+            Ok(line) => match parse_pasted(&line, state) {
+                Ok(result) => return Ok(result),
+                Err(err) => prompt(&err),
+            },
+            Err(err) => return Err(format!("Failed to read stdin: {err}")),
+        }
+    }
+}
+
+impl Login {
+    /// Waits for the callback, or the pasted redirect URL in headless mode, and returns the
+    /// authorization code. Gives up after the timeout, Ctrl+C isn't caught so it still stops the
+    /// process, during the login and after it
+    pub async fn wait(mut self) -> Result<String, String> {
+        let provider = self.provider;
+
+        if self.headless {
+            return match read_pasted(provider, &self.state, &self.prompt).await? {
+                Ok(code) => Ok(code),
+                Err(err) => Err(format!("{provider} refused the login ({err})")),
+            };
+        }
+
+        (self.prompt)(&format!("Please sign in to {provider} with the link above"));
+
+        tokio::select! {
+            result = &mut self.receiver => match result {
+                Ok(Ok(code)) => Ok(code),
+                Ok(Err(err)) => Err(format!("{provider} refused the login ({err})")),
+                Err(_) => Err(format!("The {provider} login was cancelled")),
+            },
+            _ = tokio::time::sleep(LOGIN_TIMEOUT) => {
+                Err(format!("[{}min timeout] No answer from {provider}", LOGIN_TIMEOUT.as_secs() / 60))
+            }
+        }
+    }
+}
+
+impl Drop for Login {
+    fn drop(&mut self) {
+        self.pending.lock().unwrap().remove(&self.state);
+    }
+}
+
+/// The parts of an HTTP request the callbacks care about
+#[derive(Debug)]
+struct Request {
+    method: String,
+    /// Percent-decoded path, without the query string
+    path: String,
+    query: HashMap<String, String>,
+    version: String,
+}
+
+#[derive(Debug)]
+struct Response {
+    status: u16,
+    content_type: &'static str,
+    body: String,
+    /// Outcome to hand to the waiting login once the response is sent, so the browser gets
+    /// its page even if the process exits right after
+    outcome: Option<(oneshot::Sender<CallbackResult>, CallbackResult)>,
+}
+
+impl Response {
+    fn new(status: u16, body: impl Into<String>) -> Self {
+        Self {
+            status,
+            content_type: "text/plain",
+            body: body.into(),
+            outcome: None,
+        }
+    }
+
+    /// Landing page of a login callback
+    fn page(status: u16, provider: &str, connected: bool, message: &str) -> Self {
+        let (title, color) = if connected {
+            (format!("Connected to {provider}"), "#1db954")
+        } else {
+            (format!("{provider} login failed"), "#e22134")
+        };
+
+        let body = format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<title>{title}</title>
+<style>
+body {{ font-family: system-ui, sans-serif; background: #121212; color: #eee; display: flex; align-items: center; justify-content: center; height: 100vh; margin: 0; }}
+main {{ text-align: center; max-width: 32rem; padding: 2rem; }}
+h1 {{ color: {color}; }}
+</style>
+</head>
+<body>
+<main>
+<h1>{title}</h1>
+<p>{}</p>
+<p>You can close this tab and go back to the terminal.</p>
+</main>
+</body>
+</html>
+"#,
+            escape_html(message)
+        );
+
+        Self {
+            status,
+            content_type: "text/html",
+            body,
+            outcome: None,
+        }
+    }
+
+    fn deliver(mut self, sender: oneshot::Sender<CallbackResult>, result: CallbackResult) -> Self {
+        self.outcome = Some((sender, result));
+        self
+    }
+
+    fn to_bytes(&self, version: &str) -> Vec<u8> {
+        let reason = match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            413 => "Payload Too Large",
+            _ => "Internal Server Error",
+        };
+
+        format!(
+            "{version} {} {reason}\r\nContent-Type: {}; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.status,
+            self.content_type,
+            self.body.len(),
+            self.body
+        )
+        .into_bytes()
+    }
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+/// Parses the request line (`GET /path?query HTTP/1.1`) of a request head
+fn parse_request(head: &str) -> Result<Request, String> {
+    let line = head.lines().next().unwrap_or_default();
+    let mut parts = line.split(' ').filter(|part| !part.is_empty());
+
+    let (Some(method), Some(target), Some(version), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(format!("Malformed request line \"{line}\""));
+    };
+
+    if !matches!(version, "HTTP/1.0" | "HTTP/1.1") {
+        return Err(format!("Unsupported HTTP version \"{version}\""));
+    }
+
+    let target = target.split('#').next().unwrap_or_default();
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+    Ok(Request {
+        method: method.to_owned(),
+        path: percent_decode_str(path).decode_utf8_lossy().into_owned(),
+        query: form_urlencoded::parse(query.as_bytes())
+            .into_owned()
+            .collect(),
+        version: version.to_owned(),
+    })
+}
+
+/// Reads until the end of the request head, the body (if any) is ignored
+async fn read_head(stream: &mut TcpStream) -> Result<String, Response> {
+    let mut head = Vec::new();
+    let mut buffer = [0; 1024];
+
+    loop {
+        let bytes_read = match timeout(READ_TIMEOUT, stream.read(&mut buffer)).await {
+            Ok(Ok(0)) | Err(_) => return Err(Response::new(400, "Incomplete request")),
+            Ok(Ok(n)) => n,
+            Ok(Err(err)) => return Err(Response::new(400, format!("Failed to read: {err}"))),
+        };
+
+        head.extend_from_slice(&buffer[..bytes_read]);
+
+        if let Some(end) = head.windows(4).position(|w| w == b"\r\n\r\n") {
+            head.truncate(end);
+            return Ok(String::from_utf8_lossy(&head).into_owned());
+        }
+
+        if head.len() > MAX_REQUEST_SIZE {
+            return Err(Response::new(413, "Request too large"));
+        }
+    }
+}
+
+/// Checks the callback of a provider and hands its outcome to the login waiting for it
+fn handle_callback(
+    provider: &str,
+    params: &HashMap<String, String>,
+    pending: &Pending,
+) -> Response {
+    let mut pending = pending.lock().unwrap();
 
-const SOCKET: &str = "127.0.0.1:8080";
+    // Spotify sends `error`, Deezer `error_reason`. Deezer doesn't always send the state back
+    // with errors, the error then goes to its login if there's only one. Accepting them without
+    // state can at worst abort the login
+    if let Some(err) = params.get("error").or(params.get("error_reason")) {
+        let state = params.get("state").cloned().or_else(|| {
+            let mut logins = pending.iter().filter(|(_, l)| l.provider == provider);
 
-pub struct Server;
+            match (logins.next(), logins.next()) {
+                (Some((state, _)), None) => Some(state.clone()),
+                _ => None,
+            }
+        });
+
+        let login = state
+            .filter(|state| pending.get(state).is_some_and(|l| l.provider == provider))
+            .and_then(|state| pending.remove(&state));
+
+        let description = params
+            .get("error_description")
+            .map(|description| format!(": {description}"))
+            .unwrap_or_default();
+        let response = Response::page(
+            200,
+            provider,
+            false,
+            &format!("{provider} returned the error \"{err}\"{description}."),
+        );
+
+        return match login {
+            Some(login) => response.deliver(login.sender, Err(err.to_owned())),
+            None => response,
+        };
+    }
+
+    let login = params
+        .get("state")
+        .filter(|state| pending.get(*state).is_some_and(|l| l.provider == provider))
+        .and_then(|state| pending.remove(state));
+
+    let Some(login) = login else {
+        return Response::page(
+            400,
+            provider,
+            false,
+            "This login doesn't match the one in progress, please use the link printed in the terminal.",
+        );
+    };
+
+    let Some(code) = params.get("code").filter(|code| !code.is_empty()) else {
+        return Response::page(400, provider, false, "No authorization code was sent back.")
+            .deliver(
+                login.sender,
+                Err(String::from("missing authorization code")),
+            );
+    };
+
+    // The login may have been cancelled in the meantime
+    if login.sender.is_closed() {
+        return Response::page(400, provider, false, "This login was cancelled.");
+    }
+
+    Response::page(
+        200,
+        provider,
+        true,
+        &format!("deezer_to_spotify can now access your {provider} account."),
+    )
+    .deliver(login.sender, Ok(code.to_owned()))
+}
+
+fn route(request: &Request, prefix: &str, pending: &Pending) -> Response {
+    if request.method != "GET" {
+        return Response::new(405, "Only GET is supported");
+    }
+
+    match request.path.strip_prefix(prefix) {
+        Some("/Spotify") => handle_callback("Spotify", &request.query, pending),
+        Some("/Deezer") => handle_callback("Deezer", &request.query, pending),
+        _ => Response::new(404, "Not found"),
+    }
+}
+
+async fn handle_connection(mut stream: TcpStream, prefix: Arc<str>, pending: Pending) {
+    let (response, version) = match read_head(&mut stream).await {
+        Ok(head) => match parse_request(&head) {
+            Ok(request) => (route(&request, &prefix, &pending), request.version),
+            Err(err) => (Response::new(400, err), String::from("HTTP/1.1")),
+        },
+        Err(response) => (response, String::from("HTTP/1.1")),
+    };
+
+    if let Err(err) = stream.write_all(&response.to_bytes(&version)).await {
+        log!("Server", LogCategory::Warn, "Error writing response: {err}");
+    }
+    let _ = stream.shutdown().await;
+
+    if let Some((sender, result)) = response.outcome {
+        let _ = sender.send(result);
+    }
+}
 
 impl Server {
-    pub async fn run() {
-        let listener = TcpListener::bind(SOCKET).await.unwrap();
-        let s = Arc::new(Mutex::new(false));
-        let d = Arc::new(Mutex::new(false));
+    /// Listens for the callbacks once a login needs them, so the saved sessions don't need the
+    /// port. The login links and prompts are shown with `prompt`
+    pub fn new(config: &CallbackConfig, prompt: impl Fn(&str) + Send + Sync + 'static) -> Self {
+        Self {
+            pending: Arc::new(Mutex::new(HashMap::new())),
+            config: config.clone(),
+            base_uri: Arc::new(OnceCell::new()),
+            headless: false,
+            open_browser: config.open_browser,
+            prompt: Arc::new(prompt),
+        }
+    }
+
+    /// Doesn't listen, logins wait for the redirect URL to be pasted in the terminal instead
+    pub fn headless(
+        config: &CallbackConfig,
+        prompt: impl Fn(&str) + Send + Sync + 'static,
+    ) -> Result<Self, String> {
+        if config.port == 0 {
+            return Err(String::from(
+                "The headless login needs the port of the redirect URI registered for your apps, see --callback-port",
+            ));
+        }
 
-        tokio::spawn(async move {
-            let s = Arc::clone(&s);
-            let d = Arc::clone(&d);
+        Ok(Self {
+            pending: Arc::new(Mutex::new(HashMap::new())),
+            config: config.clone(),
+            base_uri: Arc::new(OnceCell::new_with(Some(config.base_uri(config.port)))),
+            headless: true,
+            // The browser runs on another machine
+            open_browser: false,
+            prompt: Arc::new(prompt),
+        })
+    }
+
+    /// Starts listening for the callbacks in the background on the first call, returns the base URI
+    async fn listen(&self) -> Result<&str, String> {
+        self.base_uri
+            .get_or_try_init(|| async {
+                let config = &self.config;
+                // IPv6 hosts are written between brackets in URIs but not when binding
+                let host = config.host.trim_start_matches('[').trim_end_matches(']');
+                let listener = TcpListener::bind((host, config.port))
+                    .await
+                    .map_err(|err| match err.kind() {
+                        std::io::ErrorKind::AddrInUse => format!(
+                            "The callback port {} is already in use, close the program using it or pick another port with --callback-port",
+                            config.port
+                        ),
+                        _ => format!(
+                            "Failed to start the callback server on {}:{}: {err}",
+                            config.host, config.port
+                        ),
+                    })?;
+                let port = listener
+                    .local_addr()
+                    .map_err(|err| format!("Failed to get the callback server address: {err}"))?
+                    .port();
 
-            while let Ok((mut stream, _)) = listener.accept().await {
-                let mut buffer = [0; 1024];
+                let path = config.path.trim_matches('/');
+                let prefix: Arc<str> = if path.is_empty() {
+                    Arc::from("")
+                } else {
+                    Arc::from(format!("/{path}"))
+                };
+                let pending = Arc::clone(&self.pending);
 
-                if let Ok(bytes_read) = stream.read(&mut buffer).await {
-                    if bytes_read == 0 {
-                        continue;
+                tokio::spawn(async move {
+                    while let Ok((stream, _)) = listener.accept().await {
+                        tokio::spawn(handle_connection(
+                            stream,
+                            Arc::clone(&prefix),
+                            Arc::clone(&pending),
+                        ));
                     }
+                });
+
+                Ok(config.base_uri(port))
+            })
+            .await
+            .map(String::as_str)
+    }
+
+    /// Shows the authorization URL and opens it in the browser when possible
+    pub fn show_auth_url(&self, url: &str) {
+        (self.prompt)(url);
 
-                    let request = String::from_utf8_lossy(&buffer[..bytes_read]);
+        if self.open_browser {
+            if let Err(err) = webbrowser::open(url) {
+                log!(
+                    "Login",
+                    LogCategory::Warn,
+                    "Couldn't open the browser ({err}), please open the link above"
+                );
+            }
+        }
+    }
 
-                    for line in request.to_string().lines() {
-                        if line.starts_with("GET /Spotify") {
-                            let code = line
-                                .trim_start_matches("GET /Spotify?code=")
-                                .trim_end_matches(" HTTP/1.1")
-                                .to_string();
+    /// True if the port is picked by the OS, so it changes on every run
+    pub fn is_ephemeral(&self) -> bool {
+        !self.headless && self.config.port == 0
+    }
 
-                            SPOTIFY_CODE.get_or_init(|| Arc::new(RwLock::new(code)));
+    /// Starts a login to the provider, listening for the callbacks if it's the first one. The
+    /// returned state and redirect URI go in the authorization URL
+    pub async fn login(&self, provider: &'static str) -> Result<Login, String> {
+        let redirect_uri = format!("{}/{provider}", self.listen().await?);
+        let (sender, receiver) = oneshot::channel();
+        let state = random_string(32);
 
-                            let mut s = s.lock().await;
-                            *s = true;
+        self.pending
+            .lock()
+            .unwrap()
+            .insert(state.clone(), PendingLogin { provider, sender });
 
-                            break;
-                        }
+        Ok(Login {
+            state,
+            redirect_uri,
+            provider,
+            receiver,
+            pending: Arc::clone(&self.pending),
+            headless: self.headless,
+            prompt: Arc::clone(&self.prompt),
+        })
+    }
+}
 
-                        if line.starts_with("GET /Deezer") {
-                            let code = line
-                                .trim_start_matches("GET /Deezer?code=")
-                                .trim_end_matches(" HTTP/1.1")
-                                .to_string();
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-                            DEEZER_CODE.get_or_init(|| Arc::new(RwLock::new(code)));
+    fn pending_login(
+        pending: &Pending,
+        provider: &'static str,
+        state: &str,
+    ) -> oneshot::Receiver<CallbackResult> {
+        let (sender, receiver) = oneshot::channel();
+        pending
+            .lock()
+            .unwrap()
+            .insert(state.to_owned(), PendingLogin { provider, sender });
+        receiver
+    }
 
-                            let mut d = d.lock().await;
-                            *d = true;
+    fn params(query: &str) -> HashMap<String, String> {
+        form_urlencoded::parse(query.as_bytes())
+            .into_owned()
+            .collect()
+    }
 
-                            break;
-                        }
-                    }
+    #[test]
+    fn parse_request_reads_the_path_and_query() {
+        let request =
+            parse_request("GET /cb/Spotify?state=abc&extra=1&code=x%2By HTTP/1.1\r\nHost: a")
+                .unwrap();
 
-                    let response = "HTTP/1.1 200 OK\r\nContent-Length: 45\r\n\r\nYou're connected, you can close this tab now!";
-                    if let Err(err) = stream.write_all(response.as_bytes()).await {
-                        eprintln!("Error writing response: {}", err);
-                    }
-                }
+        assert_eq!(request.method, "GET");
+        assert_eq!(request.path, "/cb/Spotify");
+        assert_eq!(request.query["code"], "x+y");
+        assert_eq!(request.query["state"], "abc");
+        assert_eq!(request.version, "HTTP/1.1");
+    }
 
-                // Both apps are connected, closing server
-                if *s.lock().await && *d.lock().await {
-                    break;
-                }
+    #[test]
+    fn parse_request_accepts_http_1_0_and_drops_fragments() {
+        let request = parse_request("GET /my%20path?code=1#frag HTTP/1.0").unwrap();
+
+        assert_eq!(request.path, "/my path");
+        assert_eq!(request.query["code"], "1");
+        assert_eq!(request.version, "HTTP/1.0");
+    }
+
+    #[test]
+    fn parse_request_rejects_malformed_lines() {
+        assert!(parse_request("GET /").is_err());
+        assert!(parse_request("GET / HTTP/1.1 extra").is_err());
+        assert!(parse_request("GET / HTTP/2").is_err());
+        assert!(parse_request("").is_err());
+    }
+
+    #[tokio::test]
+    async fn read_head_joins_requests_split_across_reads() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let client = tokio::spawn(async move {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            for part in [
+                "GET /Spotify?co",
+                "de=1 HTTP/1.1\r\nHost: x\r",
+                "\n\r\nbody",
+            ] {
+                stream.write_all(part.as_bytes()).await.unwrap();
+                stream.flush().await.unwrap();
+                tokio::time::sleep(Duration::from_millis(20)).await;
             }
+            stream
+        });
+
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let head = read_head(&mut stream).await.unwrap();
+        let _client = client.await.unwrap();
+
+        assert_eq!(head, "GET /Spotify?code=1 HTTP/1.1\r\nHost: x");
+    }
+
+    #[tokio::test]
+    async fn server_binds_the_port_on_the_first_login() {
+        let taken = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = CallbackConfig {
+            port: taken.local_addr().unwrap().port(),
+            open_browser: false,
+            ..Default::default()
+        };
+
+        // Nothing is bound until a login needs the callback
+        let server = Server::new(&config, |_| {});
+        let err = server.login("Spotify").await.err().unwrap();
+        assert!(err.contains("already in use"), "{err}");
+
+        let config = CallbackConfig { port: 0, ..config };
+        let server = Server::new(&config, |_| {});
+        let spotify = server.login("Spotify").await.unwrap();
+        let deezer = server.login("Deezer").await.unwrap();
+
+        assert!(spotify.redirect_uri.ends_with("/Spotify"));
+        assert_eq!(
+            spotify.redirect_uri.trim_end_matches("/Spotify"),
+            deezer.redirect_uri.trim_end_matches("/Deezer")
+        );
+    }
+
+    #[tokio::test]
+    async fn read_head_refuses_closed_and_oversized_requests() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let client = tokio::spawn(async move {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            stream.write_all(b"GET / HTTP/1.1\r\n").await.unwrap();
         });
+        let (mut stream, _) = listener.accept().await.unwrap();
+        client.await.unwrap();
+        assert_eq!(read_head(&mut stream).await.unwrap_err().status, 400);
+
+        let client = tokio::spawn(async move {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            let _ = stream.write_all(&vec![b'a'; MAX_REQUEST_SIZE + 2048]).await;
+            stream
+        });
+        let (mut stream, _) = listener.accept().await.unwrap();
+        assert_eq!(read_head(&mut stream).await.unwrap_err().status, 413);
+        let _client = client.await.unwrap();
+    }
+
+    #[test]
+    fn handle_callback_delivers_the_code_to_its_login() {
+        let pending = Pending::default();
+        let mut receiver = pending_login(&pending, "Spotify", "abc");
+
+        let response = handle_callback("Spotify", &params("code=xyz&state=abc"), &pending);
+        assert_eq!(response.status, 200);
+
+        let (sender, result) = response.outcome.unwrap();
+        sender.send(result).unwrap();
+        assert_eq!(receiver.try_recv().unwrap(), Ok(String::from("xyz")));
+        assert!(pending.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn handle_callback_refuses_unknown_states_and_other_providers() {
+        let pending = Pending::default();
+        let _receiver = pending_login(&pending, "Deezer", "abc");
+
+        let response = handle_callback("Spotify", &params("code=xyz&state=abc"), &pending);
+        assert_eq!(response.status, 400);
+        assert!(response.outcome.is_none());
+
+        let response = handle_callback("Deezer", &params("code=xyz&state=other"), &pending);
+        assert_eq!(response.status, 400);
+        assert!(response.outcome.is_none());
+        assert_eq!(pending.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn handle_callback_hands_errors_over_even_without_state() {
+        let pending = Pending::default();
+        let _receiver = pending_login(&pending, "Deezer", "abc");
+
+        let response = handle_callback("Deezer", &params("error_reason=user_denied"), &pending);
+        assert_eq!(response.status, 200);
+
+        let (_, result) = response.outcome.unwrap();
+        assert_eq!(result, Err(String::from("user_denied")));
+    }
+
+    #[test]
+    fn parse_pasted_reads_urls_and_bare_codes() {
+        assert_eq!(
+            parse_pasted("  http://127.0.0.1:8080/Spotify?code=abc&state=s1\n", "s1"),
+            Ok(Ok(String::from("abc")))
+        );
+        assert_eq!(
+            parse_pasted("?state=s1&foo=bar&code=a%2Fb#_=_", "s1"),
+            Ok(Ok(String::from("a/b")))
+        );
+        assert_eq!(
+            parse_pasted("AQBx-yz", "s1"),
+            Ok(Ok(String::from("AQBx-yz")))
+        );
+    }
+
+    #[test]
+    fn parse_pasted_returns_provider_errors() {
+        assert_eq!(
+            parse_pasted("http://host/Deezer?error_reason=user_denied", "s1"),
+            Ok(Err(String::from("user_denied")))
+        );
+    }
+
+    #[test]
+    fn parse_pasted_refuses_other_logins_and_empty_input() {
+        assert!(parse_pasted("http://host/Spotify?code=abc&state=other", "s1").is_err());
+        assert!(parse_pasted("http://host/Spotify?state=s1", "s1").is_err());
+        assert!(parse_pasted("   ", "s1").is_err());
+    }
+
+    #[test]
+    fn handle_callback_reports_a_missing_code() {
+        let pending = Pending::default();
+        let _receiver = pending_login(&pending, "Spotify", "abc");
+
+        let response = handle_callback("Spotify", &params("state=abc&code="), &pending);
+        assert_eq!(response.status, 400);
+        assert!(response.outcome.unwrap().1.is_err());
     }
 }
@@ -1,17 +1,25 @@
 use std::{
-    sync::{Arc, OnceLock},
-    time::Duration,
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
 };
 
-use base64::{engine::general_purpose, Engine as _};
-use loading::Loading;
-use reqwest::Client;
+use futures_util::{stream, StreamExt, TryStreamExt};
+use reqwest::{header::RETRY_AFTER, Client, RequestBuilder, Response, StatusCode};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-use tokio::{sync::RwLock, time::sleep};
 
-use crate::deezer::DeezerPlaylist;
-use crate::logger::{log, LogCategory};
+use crate::config::SpotifyCredentials;
+use crate::deezer::{Deezer, DeezerPlaylist, DeezerTrack};
+use crate::json::str_at;
+use crate::logger::{self, log, LogCategory};
+use crate::matcher::{track_key, Candidate, MatchResult, MatchSource, Matcher};
+use crate::oauth::Pkce;
+use crate::progress::{self, Progress};
+use crate::report::Report;
+use crate::server::Server;
+use crate::tokens::{self, SavedToken};
+use crate::unix_time;
 
 const TOKEN_URL: &str = "https://accounts.spotify.com/api/token";
 const SCOPES: [&str; 4] = [
@@ -20,91 +28,127 @@ const SCOPES: [&str; 4] = [
     "playlist-modify-private",
     "playlist-modify-public",
 ];
-const REDIRECT_URI: &str = "http://localhost:8080/Spotify";
-
-pub static CODE: OnceLock<Arc<RwLock<String>>> = OnceLock::new();
+const TOKEN_KEY: &str = "spotify";
+/// Rate limited requests are sent again this many times before giving up
+const RATE_LIMIT_RETRIES: u32 = 5;
 
+/// Spotify API client, log in with [`crate::App::init`] before using it
 #[derive(Debug)]
 pub struct Spotify<'app> {
-    pub email: String,
-    pub password: String,
     client: &'app Client,
+    credentials: SpotifyCredentials,
     access_token: String,
+    /// Set for the Authorization Code with PKCE flow, used when no client secret is configured
+    pkce: Option<Pkce>,
+    redirect_uri: String,
+    /// Set when Spotify rate limits the app, requests wait until then
+    retry_at: Mutex<Option<Instant>>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// Most tracks Spotify takes in one request
+pub const TRACKS_PER_REQUEST: usize = 100;
+
+/// A Spotify track
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct SpotifyTrack {
-    id: String,
-    title: String,
-    artist_name: String,
+    /// Spotify id of the track
+    pub id: String,
+    /// Title of the track
+    pub title: String,
+    /// Name of the main artist
+    pub artist_name: String,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+impl SpotifyTrack {
+    /// The `spotify:track:` URI of the track
+    pub fn uri(&self) -> String {
+        format!("spotify:track:{}", self.id)
+    }
+}
+
+/// A Deezer playlist with its tracks matched on Spotify
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct SpotifyPlaylist {
+    /// Deezer id of the playlist
+    pub deezer_id: i64,
+    /// Name of the Spotify playlist
     pub title: String,
+    /// The matched tracks, in the Deezer order
     pub tracks: Vec<SpotifyTrack>,
+    /// The Deezer tracks that weren't found
+    pub unmatched: Vec<DeezerTrack>,
+}
+
+/// A playlist owned by the logged in user, as listed by Spotify (without its tracks)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SpotifyPlaylistInfo {
+    /// Spotify id of the playlist
+    pub id: String,
+    /// Name of the playlist
+    pub name: String,
+    /// Version of the playlist, it changes on every modification
+    pub snapshot_id: String,
 }
 
 #[async_trait::async_trait]
 impl<'app> crate::App for Spotify<'app> {
     type Error = String;
 
-    async fn init(&mut self) {
-        println!("{}", Spotify::get_auth_url());
+    async fn init(&mut self, server: &Server) -> Result<(), Self::Error> {
+        if self.restore_session().await {
+            log!("Spotify", LogCategory::Success, "Logged in to Spotify!");
+            return Ok(());
+        }
 
-        let spot_load = Loading::default();
-        spot_load.text(String::from(
-            "Please sign in to Spotify with the link above",
-        ));
+        if self.credentials.client_secret.is_none() {
+            self.pkce = Some(Pkce::new());
+        }
 
-        let mut timeout = 0;
-        while CODE.get().is_none() {
-            if timeout == 150 {
-                spot_load.fail(String::from("[5min timeout] Failed to login to Spotify"));
-                std::process::exit(1);
-            }
+        let login = server.login("Spotify").await?;
+        self.redirect_uri = login.redirect_uri.clone();
 
-            timeout += 1;
-            sleep(Duration::from_secs(2)).await;
-        }
+        server.show_auth_url(&self.get_auth_url(&login.state));
 
-        match self.fetch_token().await {
-            Ok(_) => spot_load.success(String::from("Logged in to Spotify!")),
-            Err(err) => {
-                spot_load.fail(format!("Failed to login to Spotify ({err})"));
-                std::process::exit(1);
-            }
-        }
+        let code = login.wait().await?;
+
+        log!("Spotify", LogCategory::Info, "Logging in to Spotify...");
+
+        self.fetch_token(&code)
+            .await
+            .map_err(|err| format!("Failed to login to Spotify ({err})"))?;
+
+        log!("Spotify", LogCategory::Success, "Logged in to Spotify!");
 
-        spot_load.end();
+        Ok(())
     }
 
-    // Keep in mind that, if you wanna use this, you need to handle the refresh token (every 60 minutes, the access token expires)
-    async fn fetch_token(&mut self) -> Result<(), Self::Error> {
-        let id = dotenv::var("SPOTIFY_CLIENT_ID")
-            .map_err(|err| format!("Failed to get Spotify client ID from env: {err}"))?;
-        let secret = dotenv::var("SPOTIFY_CLIENT_SECRET")
-            .map_err(|err| format!("Failed to get Spotify client SECRET from env: {err}"))?;
+    async fn fetch_token(&mut self, code: &str) -> Result<(), Self::Error> {
+        let id = &self.credentials.client_id;
+        let mut params = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", &self.redirect_uri),
+        ];
 
-        let res = self
-            .client
-            .post(format!(
-                "{}?grant_type=authorization_code&code={}&client_id={}&client_secret={}&redirect_uri={}",
-                TOKEN_URL,
-                CODE.get().unwrap().read().await,
-                id,
-                secret,
-                REDIRECT_URI
-            ))
-            .header(
-                "Authorization",
-                format!(
-                    "Basic {}",
-                    general_purpose::STANDARD.encode(format!("{}:{}", id, secret))
-                ),
-            )
-            .header("Content-Type", "application/x-www-form-urlencoded")
-            .header("Content-Length", "0")
+        let mut req = self.client.post(TOKEN_URL);
+
+        match &self.pkce {
+            Some(pkce) => {
+                params.push(("client_id", id));
+                params.push(("code_verifier", &pkce.verifier));
+            }
+            None => {
+                let secret = self
+                    .credentials
+                    .client_secret
+                    .as_ref()
+                    .ok_or("Missing Spotify client secret")?;
+                req = req.basic_auth(id, Some(secret));
+            }
+        }
+
+        let res = req
+            .form(&params)
             .send()
             .await
             .map_err(|err| format!("Failed to send Spotify token request: {err}"))?;
@@ -122,115 +166,436 @@ impl<'app> crate::App for Spotify<'app> {
             .await
             .map_err(|err| format!("Failed to get Spotify token json result: {err}"))?;
 
-        self.access_token = body["access_token"]
-            .as_str()
-            .ok_or_else(|| format!("Failed to get Spotify access token from json result: {body}"))?
-            .to_owned();
+        self.save_token(&body, None)
+    }
 
-        Ok(())
+    async fn restore_session(&mut self) -> bool {
+        let Some(saved) = tokens::load(TOKEN_KEY) else {
+            return false;
+        };
+
+        if !saved.is_expired() {
+            self.access_token = saved.access_token;
+            logger::add_secret(&self.access_token);
+
+            if self.get_my_id().await.is_ok() {
+                return true;
+            }
+        }
+
+        let Some(refresh_token) = saved.refresh_token else {
+            return false;
+        };
+
+        if let Err(err) = self.refresh_token(refresh_token).await {
+            log!(
+                "Spotify",
+                LogCategory::Warn,
+                "Couldn't reuse the saved Spotify session: {err}"
+            );
+            return false;
+        }
+
+        self.get_my_id().await.is_ok()
     }
 
-    fn get_auth_url() -> String {
-        let id = dotenv::var("SPOTIFY_CLIENT_ID")
-            .map_err(|err| format!("Failed to get Spotify client ID from env {err}"))
-            .unwrap();
+    fn get_auth_url(&self, state: &str) -> String {
         let scopes = SCOPES.join("%20");
+        let mut url = format!(
+            "https://accounts.spotify.com/authorize?client_id={}&response_type=code&show_dialog=true&redirect_uri={}&scope={}&state={}",
+            self.credentials.client_id,
+            form_urlencoded::byte_serialize(self.redirect_uri.as_bytes()).collect::<String>(),
+            scopes,
+            state
+        );
+
+        if let Some(pkce) = &self.pkce {
+            url.push_str(&format!(
+                "&code_challenge_method=S256&code_challenge={}",
+                pkce.challenge
+            ));
+        }
 
-        format!(
-            "https://accounts.spotify.com/authorize?client_id={}&response_type=code&show_dialog=true&redirect_uri={}&scope={}",
-            id, REDIRECT_URI, scopes
-        )
+        url
     }
 }
 
 impl<'app> Spotify<'app> {
-    pub fn new(client: &'app Client) -> Self {
+    /// A client that isn't logged in yet
+    pub fn new(client: &'app Client, credentials: SpotifyCredentials) -> Self {
         Self {
-            email: String::new(),
-            password: String::new(),
             client,
+            credentials,
             access_token: String::new(),
+            pkce: None,
+            redirect_uri: String::new(),
+            retry_at: Mutex::new(None),
+        }
+    }
+
+    /// Sends a Web API request, waiting and sending it again when Spotify rate limits the app. The
+    /// wait applies to every request, so concurrent searches back off together
+    async fn send(&self, request: RequestBuilder) -> Result<Response, reqwest::Error> {
+        let mut retries = 0;
+
+        loop {
+            let wait = self
+                .retry_at
+                .lock()
+                .unwrap()
+                .and_then(|at| at.checked_duration_since(Instant::now()));
+            if let Some(wait) = wait {
+                tokio::time::sleep(wait).await;
+            }
+
+            // Only bodiless requests go through here, they can always be cloned
+            let req = request.try_clone().unwrap().build()?;
+            log!(
+                "Spotify",
+                LogCategory::Trace,
+                "{} {}",
+                req.method(),
+                req.url()
+            );
+
+            let res = self.client.execute(req).await?;
+
+            if res.status() != StatusCode::TOO_MANY_REQUESTS || retries == RATE_LIMIT_RETRIES {
+                return Ok(res);
+            }
+
+            let seconds = res
+                .headers()
+                .get(RETRY_AFTER)
+                .and_then(|value| value.to_str().ok()?.parse().ok())
+                .unwrap_or(1);
+
+            log!(
+                "Spotify",
+                LogCategory::Warn,
+                "Rate limited, waiting {seconds}s"
+            );
+
+            *self.retry_at.lock().unwrap() = Some(Instant::now() + Duration::from_secs(seconds));
+            retries += 1;
+        }
+    }
+
+    /// Checks the client id and secret with the Client Credentials flow. Without secret, the client id
+    /// can only be checked by logging in. Returns false if they're refused
+    pub async fn check_credentials(&self) -> Result<bool, <Spotify<'app> as crate::App>::Error> {
+        let Some(secret) = &self.credentials.client_secret else {
+            return Ok(true);
+        };
+
+        let res = self
+            .client
+            .post(TOKEN_URL)
+            .basic_auth(&self.credentials.client_id, Some(secret))
+            .form(&[("grant_type", "client_credentials")])
+            .send()
+            .await
+            .map_err(|err| format!("Failed to send Spotify token request: {err}"))?;
+
+        if res.status().is_client_error() {
+            log!(
+                "Spotify",
+                LogCategory::Warn,
+                "Spotify refused the client id or secret: ({}) {:?}",
+                res.status(),
+                res.text().await
+            );
+            return Ok(false);
+        }
+        if !res.status().is_success() {
+            return Err(format!(
+                "Failed to check the Spotify credentials: ({}) {:?}",
+                res.status(),
+                res.text().await
+            ));
+        }
+
+        Ok(true)
+    }
+
+    /// Uses the access token of a token endpoint response and saves it for the next runs.
+    /// Spotify doesn't always send a new refresh token when refreshing, the previous one stays valid then.
+    fn save_token(
+        &mut self,
+        body: &serde_json::Value,
+        previous_refresh_token: Option<String>,
+    ) -> Result<(), <Spotify<'app> as crate::App>::Error> {
+        self.access_token = body["access_token"]
+            .as_str()
+            .ok_or_else(|| format!("Failed to get Spotify access token from json result: {body}"))?
+            .to_owned();
+        logger::add_secret(&self.access_token);
+
+        let refresh_token = body["refresh_token"]
+            .as_str()
+            .map(str::to_owned)
+            .or(previous_refresh_token);
+
+        if let Err(err) = tokens::save(
+            TOKEN_KEY,
+            SavedToken {
+                access_token: self.access_token.clone(),
+                refresh_token,
+                expires_at: body["expires_in"]
+                    .as_u64()
+                    .map(|expires_in| unix_time().as_secs() + expires_in),
+            },
+        ) {
+            log!(
+                "Spotify",
+                LogCategory::Warn,
+                "Couldn't save the Spotify token: {err}"
+            );
+        }
+
+        Ok(())
+    }
+
+    async fn refresh_token(
+        &mut self,
+        refresh_token: String,
+    ) -> Result<(), <Spotify<'app> as crate::App>::Error> {
+        let id = &self.credentials.client_id;
+        let mut params = vec![
+            ("grant_type", "refresh_token"),
+            ("refresh_token", refresh_token.as_str()),
+        ];
+
+        let mut req = self.client.post(TOKEN_URL);
+
+        // Tokens obtained with PKCE are refreshed with the client id instead of the secret
+        match &self.credentials.client_secret {
+            Some(secret) => req = req.basic_auth(id, Some(secret)),
+            None => params.push(("client_id", id)),
         }
+
+        let res = req
+            .form(&params)
+            .send()
+            .await
+            .map_err(|err| format!("Failed to send Spotify refresh token request: {err}"))?;
+
+        if !res.status().is_success() {
+            return Err(format!(
+                "Failed to refresh Spotify token: ({}) {:?}",
+                res.status(),
+                res.text().await
+            ));
+        }
+
+        let body: serde_json::Value = res
+            .json()
+            .await
+            .map_err(|err| format!("Failed to get Spotify refresh token json result: {err}"))?;
+
+        self.save_token(&body, Some(refresh_token))
     }
 
+    /// Searches tracks with a Spotify search query, e.g. `track:"Title" artist:"Artist"`
+    pub async fn search_tracks(
+        &self,
+        query: &str,
+        limit: u32,
+    ) -> Result<Vec<Candidate>, <Spotify<'app> as crate::App>::Error> {
+        let res = self
+            .send(
+                self.client
+                    .get("https://api.spotify.com/v1/search")
+                    .query(&[
+                        ("q", query),
+                        ("type", "track"),
+                        ("limit", &limit.to_string()),
+                    ])
+                    .header("Authorization", format!("Bearer {}", self.access_token)),
+            )
+            .await
+            .map_err(|err| format!("Failed to send Spotify search request: {err}"))?;
+
+        if !res.status().is_success() {
+            return Err(format!(
+                "Failed to fetch Spotify search: ({}) {:?}",
+                res.status(),
+                res.text().await
+            ));
+        }
+
+        let body: serde_json::Value = res
+            .json()
+            .await
+            .map_err(|err| format!("Failed to get Spotify search json result: {err}"))?;
+
+        let mut v = Vec::new();
+
+        for item in body["tracks"]["items"].as_array().unwrap_or(&Vec::new()) {
+            if item["type"].as_str().is_some_and(|t| t == "track") {
+                v.push(Candidate {
+                    track: SpotifyTrack {
+                        id: str_at(item, "/id", "Spotify search")?,
+                        title: str_at(item, "/name", "Spotify search")?,
+                        artist_name: str_at(item, "/artists/0/name", "Spotify search")?,
+                    },
+                    album_title: item["album"]["name"].as_str().map(str::to_owned),
+                    duration_ms: item["duration_ms"].as_u64(),
+                    isrc: item["external_ids"]["isrc"].as_str().map(str::to_owned),
+                });
+            }
+        }
+
+        Ok(v)
+    }
+
+    /// Fetches a track by its Spotify id
+    pub async fn get_track(
+        &self,
+        id: &str,
+    ) -> Result<SpotifyTrack, <Spotify<'app> as crate::App>::Error> {
+        let res = self
+            .send(
+                self.client
+                    .get(format!("https://api.spotify.com/v1/tracks/{id}"))
+                    .header("Authorization", format!("Bearer {}", self.access_token)),
+            )
+            .await
+            .map_err(|err| format!("Failed to send Spotify track request: {err}"))?;
+
+        if !res.status().is_success() {
+            return Err(format!(
+                "Failed to fetch Spotify track {id}: ({}) {:?}",
+                res.status(),
+                res.text().await
+            ));
+        }
+
+        let body: serde_json::Value = res
+            .json()
+            .await
+            .map_err(|err| format!("Failed to get Spotify track json result: {err}"))?;
+
+        Ok(SpotifyTrack {
+            id: str_at(&body, "/id", "Spotify track")?,
+            title: str_at(&body, "/name", "Spotify track")?,
+            artist_name: str_at(&body, "/artists/0/name", "Spotify track")?,
+        })
+    }
+
+    /// Finds the tracks of the Deezer playlists on Spotify, in order. Unmatched tracks are kept in
+    /// [`SpotifyPlaylist::unmatched`] and the ones that need attention are recorded in `report`.
+    ///
+    /// Up to [`Matcher::searches`] tracks are searched at the same time, a track found in several
+    /// playlists is only searched once. `deezer` fetches the ISRCs of the tracks that aren't known
+    /// already, leave it out for playlists that don't come from Deezer
     pub async fn get_tracks_from_deezer(
         &self,
-        playlist: Vec<DeezerPlaylist>,
+        playlists: Vec<DeezerPlaylist>,
+        deezer: Option<&Deezer<'_>>,
+        matcher: &Matcher,
+        report: &mut Report,
     ) -> Result<Vec<SpotifyPlaylist>, <Spotify<'app> as crate::App>::Error> {
+        let mut unique: Vec<&DeezerTrack> = Vec::new();
+        let mut indexes = HashMap::new();
+
+        for track in playlists.iter().flat_map(|playlist| &playlist.tracks) {
+            indexes.entry(track_key(track)).or_insert_with(|| {
+                unique.push(track);
+                unique.len() - 1
+            });
+        }
+
+        log!(
+            "Spotify",
+            LogCategory::Debug,
+            "Searching {} track(s) of {} playlist(s)",
+            unique.len(),
+            playlists.len()
+        );
+
+        progress::emit(Progress::Searching {
+            tracks: unique.len(),
+        });
+
+        // `buffered` keeps the results in the order of the tracks
+        let results: Vec<MatchResult> = stream::iter(unique)
+            .map(|track| async move {
+                let result = matcher.find(self, deezer, track).await?;
+                progress::emit(Progress::Searched {
+                    found: result.track.is_some(),
+                });
+
+                Ok::<_, String>(result)
+            })
+            .buffered(matcher.searches.max(1))
+            .try_collect()
+            .await?;
+
         let mut p = Vec::new();
 
-        for playlist in playlist {
+        for playlist in playlists {
             let mut curr_playlist = SpotifyPlaylist {
+                deezer_id: playlist.id,
                 title: playlist.title.clone(),
                 tracks: Vec::new(),
+                unmatched: Vec::new(),
             };
 
             log!(
                 "Spotify",
-                LogCategory::Info,
-                "Fetching playlist \"{}\" tracks",
+                LogCategory::Debug,
+                { playlist: playlist.title },
+                "Matching playlist \"{}\" tracks",
                 playlist.title
             );
 
             for track in playlist.tracks {
-                let res = self
-                    .client
-                    .get(format!(
-                        "https://api.spotify.com/v1/search?q={}%20artist:{}&type=track&limit=1",
-                        track.title,
-                        track.artist_name.replace(' ', "%20")
-                    ))
-                    .header("Authorization", format!("Bearer {}", self.access_token))
-                    .send()
-                    .await
-                    .map_err(|err| format!("Failed to send Spotify search request: {err}"))?;
-
-                if !res.status().is_success() {
-                    return Err(format!(
-                        "Failed to fetch Spotify search: ({}) {:?}",
-                        res.status(),
-                        res.text().await
-                    ));
-                }
+                let result = &results[indexes[&track_key(&track)]];
 
-                let body: serde_json::Value = res
-                    .json()
-                    .await
-                    .map_err(|err| format!("Failed to get Spotify search json result: {err}"))?;
+                report.record(
+                    &playlist.title,
+                    &track,
+                    result,
+                    result.is_low_confidence(&matcher.thresholds),
+                );
 
-                let items_found = body["tracks"]["items"].as_array();
-
-                if items_found.is_none() {
+                if result.source == MatchSource::Skipped {
                     log!(
                         "Spotify",
-                        LogCategory::Info,
-                        "Track not found on Spotify: {} by {}",
+                        LogCategory::Debug,
+                        { playlist: playlist.title, track: track.title },
+                        "Skipping track {} by {} (override)",
                         track.title,
                         track.artist_name
                     );
-                    // println!("DEBUG: {:#?}", body);
                     continue;
                 }
 
-                for item in items_found.unwrap() {
-                    if item["type"].as_str().is_some_and(|t| t == "track") {
-                        let found_track = SpotifyTrack {
-                            id: item["id"].as_str().unwrap().to_owned(),
-                            title: item["name"].as_str().unwrap().to_owned(),
-                            artist_name: item["artists"][0]["name"].as_str().unwrap().to_owned(),
-                        };
-
-                        log!(
-                            "Spotify",
-                            LogCategory::Info,
-                            "| Found track \"{}\" by \"{}\" on Spotify",
-                            found_track.title,
-                            found_track.artist_name
-                        );
-
-                        curr_playlist.tracks.push(found_track);
-                    }
-                }
+                let Some(found_track) = result.track.clone() else {
+                    log!(
+                        "Spotify",
+                        LogCategory::Warn,
+                        { playlist: playlist.title, track: track.title },
+                        "Track not found on Spotify: {} by {}",
+                        track.title,
+                        track.artist_name
+                    );
+                    curr_playlist.unmatched.push(track);
+                    continue;
+                };
+
+                log!(
+                    "Spotify",
+                    LogCategory::Debug,
+                    { playlist: playlist.title, track: track.title },
+                    "| Found track \"{}\" by \"{}\" on Spotify ({:.0}%)",
+                    found_track.title,
+                    found_track.artist_name,
+                    result.confidence * 100.0
+                );
+
+                curr_playlist.tracks.push(found_track);
             }
 
             p.push(curr_playlist);
@@ -239,6 +604,7 @@ impl<'app> Spotify<'app> {
         Ok(p)
     }
 
+    /// Id of the logged in user
     pub async fn get_my_id(&self) -> Result<String, <Spotify<'app> as crate::App>::Error> {
         let res = self
             .client
@@ -261,34 +627,31 @@ impl<'app> Spotify<'app> {
             .await
             .map_err(|err| format!("Failed to get Spotify user info json result: {err}"))?;
 
-        Ok(body["id"].as_str().unwrap().to_owned())
+        str_at(&body, "/id", "Spotify user info")
     }
 
-    pub async fn create_playlists(
+    /// Lists the playlists owned by the logged in user
+    pub async fn get_my_playlists(
         &self,
-        playlists: Vec<SpotifyPlaylist>,
-    ) -> Result<(), <Spotify<'app> as crate::App>::Error> {
-        let id = self.get_my_id().await?;
+    ) -> Result<Vec<SpotifyPlaylistInfo>, <Spotify<'app> as crate::App>::Error> {
+        let user_id = self.get_my_id().await?;
+        let mut next = Some(String::from(
+            "https://api.spotify.com/v1/me/playlists?limit=50",
+        ));
+        let mut v = Vec::new();
 
-        for playlist in playlists {
+        while let Some(url) = next {
             let res = self
                 .client
-                .post(format!("https://api.spotify.com/v1/users/{id}/playlists"))
+                .get(url)
                 .header("Authorization", format!("Bearer {}", self.access_token))
-                .json(&json!({
-                    "name": playlist.title,
-                    "description": "",
-                    "public": false
-                }))
                 .send()
                 .await
-                .map_err(|err| {
-                    format!("Couldn't send Spotify post resquest to create playlist {err}")
-                })?;
+                .map_err(|err| format!("Failed to send Spotify playlists request: {err}"))?;
 
             if !res.status().is_success() {
                 return Err(format!(
-                    "Failed to create Spotify playlist: ({}) {:?}",
+                    "Failed to fetch Spotify playlists: ({}) {:?}",
                     res.status(),
                     res.text().await
                 ));
@@ -297,23 +660,134 @@ impl<'app> Spotify<'app> {
             let body: serde_json::Value = res
                 .json()
                 .await
-                .map_err(|err| format!("Failed to get Spotify playlist json result: {err}"))?;
+                .map_err(|err| format!("Failed to get Spotify playlists json result: {err}"))?;
+
+            for playlist in body["items"].as_array().unwrap_or(&Vec::new()) {
+                if playlist["owner"]["id"].as_str() != Some(user_id.as_str()) {
+                    continue;
+                }
 
-            let playlist_id = body["id"].as_str().unwrap().to_owned();
+                v.push(SpotifyPlaylistInfo {
+                    id: str_at(playlist, "/id", "Spotify playlists")?,
+                    name: str_at(playlist, "/name", "Spotify playlists")?,
+                    snapshot_id: str_at(playlist, "/snapshot_id", "Spotify playlists")?,
+                });
+            }
 
-            let uris = playlist
-                .tracks
-                .iter()
-                .map(|t| format!("spotify:track:{}", t.id))
-                .collect::<Vec<String>>();
+            next = body["next"].as_str().map(str::to_owned);
+        }
 
+        Ok(v)
+    }
+
+    /// Every track of the playlist, in order
+    pub async fn get_playlist_tracks(
+        &self,
+        playlist_id: &str,
+    ) -> Result<Vec<SpotifyTrack>, <Spotify<'app> as crate::App>::Error> {
+        let mut next = Some(format!(
+            "https://api.spotify.com/v1/playlists/{playlist_id}/tracks?limit=100"
+        ));
+        let mut v = Vec::new();
+
+        while let Some(url) = next {
+            let res = self
+                .client
+                .get(url)
+                .header("Authorization", format!("Bearer {}", self.access_token))
+                .send()
+                .await
+                .map_err(|err| format!("Failed to send Spotify playlist tracks request: {err}"))?;
+
+            if !res.status().is_success() {
+                return Err(format!(
+                    "Failed to fetch Spotify playlist tracks: ({}) {:?}",
+                    res.status(),
+                    res.text().await
+                ));
+            }
+
+            let body: serde_json::Value = res.json().await.map_err(|err| {
+                format!("Failed to get Spotify playlist tracks json result: {err}")
+            })?;
+
+            for item in body["items"].as_array().unwrap_or(&Vec::new()) {
+                let track = &item["track"];
+
+                // Local files and removed tracks don't have an id
+                let Some(id) = track["id"].as_str() else {
+                    continue;
+                };
+
+                v.push(SpotifyTrack {
+                    id: id.to_owned(),
+                    title: track["name"].as_str().unwrap_or_default().to_owned(),
+                    artist_name: track["artists"][0]["name"]
+                        .as_str()
+                        .unwrap_or_default()
+                        .to_owned(),
+                });
+            }
+
+            next = body["next"].as_str().map(str::to_owned);
+        }
+
+        Ok(v)
+    }
+
+    /// Creates an empty private playlist and returns its id
+    pub async fn create_playlist(
+        &self,
+        user_id: &str,
+        title: &str,
+    ) -> Result<String, <Spotify<'app> as crate::App>::Error> {
+        let res = self
+            .client
+            .post(format!(
+                "https://api.spotify.com/v1/users/{user_id}/playlists"
+            ))
+            .header("Authorization", format!("Bearer {}", self.access_token))
+            .json(&json!({
+                "name": title,
+                "description": "",
+                "public": false
+            }))
+            .send()
+            .await
+            .map_err(|err| {
+                format!("Couldn't send Spotify post resquest to create playlist {err}")
+            })?;
+
+        if !res.status().is_success() {
+            return Err(format!(
+                "Failed to create Spotify playlist: ({}) {:?}",
+                res.status(),
+                res.text().await
+            ));
+        }
+
+        let body: serde_json::Value = res
+            .json()
+            .await
+            .map_err(|err| format!("Failed to get Spotify playlist json result: {err}"))?;
+
+        str_at(&body, "/id", "Spotify playlist")
+    }
+
+    /// Appends the tracks to the playlist, by batches of the maximum allowed by Spotify
+    pub async fn add_tracks(
+        &self,
+        playlist_id: &str,
+        uris: &[String],
+    ) -> Result<(), <Spotify<'app> as crate::App>::Error> {
+        for batch in uris.chunks(TRACKS_PER_REQUEST) {
             let res = self
                 .client
                 .post(format!(
                     "https://api.spotify.com/v1/playlists/{playlist_id}/tracks",
                 ))
                 .header("Authorization", format!("Bearer {}", self.access_token))
-                .json(&json!({ "uris": uris }))
+                .json(&json!({ "uris": batch }))
                 .send()
                 .await
                 .map_err(|err| {
@@ -331,14 +805,49 @@ impl<'app> Spotify<'app> {
                     res.text().await
                 ));
             }
+        }
 
-            log!(
-                "Spotify",
-                LogCategory::Success,
-                "Created playlist \"{}\"",
-                playlist.title
-            );
+        Ok(())
+    }
+
+    /// Removes every occurrence of the tracks from the playlist
+    pub async fn remove_tracks(
+        &self,
+        playlist_id: &str,
+        uris: &[String],
+    ) -> Result<(), <Spotify<'app> as crate::App>::Error> {
+        for batch in uris.chunks(TRACKS_PER_REQUEST) {
+            let tracks = batch
+                .iter()
+                .map(|uri| json!({ "uri": uri }))
+                .collect::<Vec<_>>();
+
+            let res = self
+                .client
+                .delete(format!(
+                    "https://api.spotify.com/v1/playlists/{playlist_id}/tracks",
+                ))
+                .header("Authorization", format!("Bearer {}", self.access_token))
+                .json(&json!({ "tracks": tracks }))
+                .send()
+                .await
+                .map_err(|err| {
+                    format!(
+                        "Couldn't send Spotify delete resquest to remove tracks from playlist id: {} {}",
+                        playlist_id, err
+                    )
+                })?;
+
+            if !res.status().is_success() {
+                return Err(format!(
+                    "Failed to remove tracks from Spotify playlist id: {} ({}) {:?}",
+                    playlist_id,
+                    res.status(),
+                    res.text().await
+                ));
+            }
         }
+
         Ok(())
     }
 }
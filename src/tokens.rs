@@ -0,0 +1,59 @@
+use std::{collections::BTreeMap, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::write_private;
+use crate::unix_time;
+
+/// OAuth tokens of a provider, saved so the user doesn't have to log in on every run
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SavedToken {
+    pub access_token: String,
+    #[serde(default)]
+    pub refresh_token: Option<String>,
+    /// Unix timestamp (seconds) after which the access token is invalid, `None` if it never expires
+    #[serde(default)]
+    pub expires_at: Option<u64>,
+}
+
+impl SavedToken {
+    /// True if the access token expires in less than a minute
+    pub fn is_expired(&self) -> bool {
+        self.expires_at
+            .is_some_and(|at| at <= unix_time().as_secs() + 60)
+    }
+}
+
+/// `~/.config/deezer_to_spotify/tokens.json` on Linux, the platform equivalent elsewhere
+fn path() -> Option<PathBuf> {
+    Some(
+        dirs::config_dir()?
+            .join("deezer_to_spotify")
+            .join("tokens.json"),
+    )
+}
+
+fn read_all() -> BTreeMap<String, SavedToken> {
+    path()
+        .and_then(|path| std::fs::read_to_string(path).ok())
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default()
+}
+
+/// The saved token of the provider, if any
+pub fn load(provider: &str) -> Option<SavedToken> {
+    read_all().remove(provider)
+}
+
+/// Saves the token of the provider in a file only readable by the current user
+pub fn save(provider: &str, token: SavedToken) -> Result<(), String> {
+    let path = path().ok_or("Failed to find the user config directory to save the tokens")?;
+    let mut tokens = read_all();
+
+    tokens.insert(provider.to_owned(), token);
+
+    let json = serde_json::to_string_pretty(&tokens)
+        .map_err(|err| format!("Failed to serialize the tokens: {err}"))?;
+
+    write_private(&path, &json)
+}
@@ -0,0 +1,382 @@
+use futures_util::{stream, StreamExt};
+use tokio::sync::mpsc;
+
+use crate::config::Naming;
+use crate::deezer::{Deezer, DeezerPlaylist, DeezerPlaylistInfo};
+use crate::journal::Journal;
+use crate::logger::{self, log, LogCategory};
+use crate::matcher::Matcher;
+use crate::plan::{Plan, PlaylistAction, PlaylistPlan};
+use crate::progress::{self, Progress};
+use crate::report::Report;
+use crate::spotify::{
+    Spotify, SpotifyPlaylist, SpotifyPlaylistInfo, SpotifyTrack, TRACKS_PER_REQUEST,
+};
+
+/// How many playlists are fetched ahead of the one being matched
+const FETCH_AHEAD: usize = 2;
+
+/// A playlist to transfer, already read from a file or still to fetch from Deezer
+#[derive(Debug)]
+pub enum Pending {
+    /// Read from a file, with its tracks
+    Read(DeezerPlaylist),
+    /// Listed on Deezer, its tracks are fetched when it's transferred
+    Deezer(DeezerPlaylistInfo),
+}
+
+impl Pending {
+    /// Deezer id of the playlist, 0 when it doesn't come from Deezer
+    pub fn id(&self) -> i64 {
+        match self {
+            Pending::Read(playlist) => playlist.id,
+            Pending::Deezer(info) => info.id,
+        }
+    }
+
+    /// Title of the playlist
+    pub fn title(&self) -> &str {
+        match self {
+            Pending::Read(playlist) => &playlist.title,
+            Pending::Deezer(info) => &info.title,
+        }
+    }
+
+    /// Fetches the tracks of the Deezer ones
+    pub async fn fetch(self, deezer: &Deezer<'_>) -> Result<DeezerPlaylist, String> {
+        match self {
+            Pending::Read(playlist) => Ok(playlist),
+            Pending::Deezer(info) => deezer.get_playlist(info).await,
+        }
+    }
+}
+
+/// A playlist ready to be matched, or already matched by a previous run
+enum Fetched {
+    /// True when it was fetched from Deezer, the ISRCs of its tracks can be fetched then
+    Playlist(DeezerPlaylist, bool),
+    Matched(SpotifyPlaylist),
+}
+
+/// Where the matched playlists go
+#[derive(Debug)]
+pub enum Target<'p> {
+    /// Created on the Spotify account, each step is recorded in the journal
+    Spotify(&'p mut Journal),
+    /// Added to the plan, nothing is written on Spotify
+    Plan(&'p mut Plan),
+}
+
+/// What happened to a playlist of a transfer
+#[derive(Debug)]
+pub struct Outcome {
+    /// Title of the Deezer playlist
+    pub title: String,
+    /// The number of matched and unmatched tracks, or why the playlist failed
+    pub result: Result<(usize, usize), String>,
+}
+
+impl Outcome {
+    /// The error is redacted, it often ends with the URL of the failed request and its token
+    fn new(title: String, result: Result<(usize, usize), String>) -> Self {
+        Self {
+            title,
+            result: result.map_err(|err| logger::redact(&err)),
+        }
+    }
+
+    fn emit(&self) {
+        let (matched, unmatched) = self.result.as_ref().copied().unwrap_or_default();
+
+        progress::emit(Progress::Finished {
+            title: &self.title,
+            matched,
+            unmatched,
+            error: self.result.as_ref().err().map(String::as_str),
+        });
+    }
+}
+
+/// Transfers the playlists one by one: each is fetched, matched on Spotify, named with `naming` and
+/// written to `target` as soon as it's ready, while the next ones are fetched.
+///
+/// A failing playlist doesn't stop the others, see the returned outcomes. Tracks that need attention
+/// are recorded in `report` and the match cache is saved after each playlist. The steps already
+/// recorded in the journal of a Spotify target are skipped
+pub async fn transfer(
+    deezer: &Deezer<'_>,
+    spotify: &Spotify<'_>,
+    playlists: Vec<Pending>,
+    matcher: &Matcher,
+    naming: &Naming,
+    report: &mut Report,
+    mut target: Target<'_>,
+) -> Result<Vec<Outcome>, String> {
+    let user_id = match &mut target {
+        Target::Spotify(journal) => {
+            let user_id = spotify.get_my_id().await?;
+            journal.set_user(&user_id)?;
+            user_id
+        }
+        Target::Plan(plan) => plan.user_id.clone(),
+    };
+    // The plans list them on their own
+    let existing = match &target {
+        Target::Spotify(_) if naming.replace_existing => spotify.get_my_playlists().await?,
+        _ => Vec::new(),
+    };
+    let mut outcomes = Vec::new();
+    let mut remaining = Vec::new();
+
+    progress::emit(Progress::Started {
+        playlists: playlists.len(),
+    });
+
+    for playlist in playlists {
+        let entry = match &target {
+            Target::Spotify(journal) => journal.get(playlist.id(), playlist.title()),
+            Target::Plan(_) => None,
+        };
+
+        match entry {
+            Some(entry) if entry.done => {
+                log!(
+                    "Transfer",
+                    LogCategory::Info,
+                    { playlist: entry.title },
+                    "Already transferred \"{}\"",
+                    entry.title
+                );
+                let matched = entry.matched.as_ref();
+                let outcome = Outcome {
+                    title: entry.title.clone(),
+                    result: Ok((
+                        matched.map_or(0, |m| m.tracks.len()),
+                        matched.map_or(0, |m| m.unmatched.len()),
+                    )),
+                };
+
+                outcome.emit();
+                outcomes.push(outcome);
+            }
+            Some(entry) => remaining.push((playlist, entry.matched.clone())),
+            None => remaining.push((playlist, None)),
+        }
+    }
+
+    // Recorded before anything can fail, so the journal is kept for --resume until each one is done
+    if let Target::Spotify(journal) = &mut target {
+        for (playlist, _) in &remaining {
+            journal.update(playlist.id(), playlist.title(), |_| {})?;
+        }
+    }
+
+    // The fetches run next to the matching and the writing, handing over the playlists in order
+    let (sender, mut receiver) = mpsc::channel(1);
+    let fetch = async move {
+        // Playlists matched before the run was stopped aren't fetched again
+        let mut fetched = stream::iter(remaining)
+            .map(|(playlist, matched)| async move {
+                let (id, title) = (playlist.id(), playlist.title().to_owned());
+                let from_deezer = matches!(playlist, Pending::Deezer(_));
+                let fetched = match matched {
+                    Some(matched) => Ok(Fetched::Matched(matched)),
+                    None => playlist
+                        .fetch(deezer)
+                        .await
+                        .map(|playlist| Fetched::Playlist(playlist, from_deezer)),
+                };
+
+                (id, title, fetched)
+            })
+            .buffered(FETCH_AHEAD);
+
+        while let Some(playlist) = fetched.next().await {
+            if sender.send(playlist).await.is_err() {
+                break;
+            }
+        }
+    };
+    let process = async {
+        while let Some((id, title, fetched)) = receiver.recv().await {
+            progress::emit(Progress::Playlist { title: &title });
+
+            let result = async {
+                let matched = match fetched? {
+                    Fetched::Playlist(playlist, from_deezer) => {
+                        let deezer = from_deezer.then_some(deezer);
+                        let matched =
+                            match_playlist(spotify, deezer, playlist, matcher, naming, report)
+                                .await?;
+
+                        if let Target::Spotify(journal) = &mut target {
+                            journal.update(id, &title, |entry| {
+                                entry.matched = Some(matched.clone())
+                            })?;
+                        }
+
+                        matched
+                    }
+                    Fetched::Matched(matched) => matched,
+                };
+
+                write(
+                    spotify,
+                    &user_id,
+                    &existing,
+                    id,
+                    &title,
+                    matched,
+                    &mut target,
+                )
+                .await
+            }
+            .await;
+
+            if let Err(err) = &result {
+                log!(
+                    "Transfer",
+                    LogCategory::Error,
+                    { playlist: title },
+                    "Failed to transfer \"{title}\": {err}"
+                );
+            }
+
+            let outcome = Outcome::new(title, result);
+
+            outcome.emit();
+            outcomes.push(outcome);
+        }
+    };
+
+    tokio::join!(fetch, process);
+
+    if let Target::Spotify(journal) = &target {
+        journal.finish()?;
+    }
+
+    Ok(outcomes)
+}
+
+/// Matches the tracks of the Deezer playlist on Spotify and names it with `naming`. The match cache
+/// is saved even if the matching fails halfway, so the next run doesn't search the same tracks again
+async fn match_playlist(
+    spotify: &Spotify<'_>,
+    deezer: Option<&Deezer<'_>>,
+    playlist: DeezerPlaylist,
+    matcher: &Matcher,
+    naming: &Naming,
+    report: &mut Report,
+) -> Result<SpotifyPlaylist, String> {
+    let matched = spotify
+        .get_tracks_from_deezer(vec![playlist], deezer, matcher, report)
+        .await;
+
+    matcher.cache.save()?;
+
+    let mut playlist = matched?.remove(0);
+    playlist.title = naming.playlist_name(&playlist.title);
+
+    Ok(playlist)
+}
+
+/// Writes the matched playlist of the Deezer playlist `id` / `title` to the target, following the
+/// policy of [`PlaylistPlan::new`] like the plans
+async fn write(
+    spotify: &Spotify<'_>,
+    user_id: &str,
+    existing: &[SpotifyPlaylistInfo],
+    id: i64,
+    title: &str,
+    playlist: SpotifyPlaylist,
+    target: &mut Target<'_>,
+) -> Result<(usize, usize), String> {
+    let counts = (playlist.tracks.len(), playlist.unmatched.len());
+
+    let journal = match target {
+        Target::Spotify(journal) => journal,
+        Target::Plan(plan) => {
+            plan.add(spotify, playlist).await?;
+            return Ok(counts);
+        }
+    };
+    let entry = journal.get(id, title);
+    let added = entry.map_or(0, |entry| entry.added);
+    let cleared = entry.is_some_and(|entry| entry.cleared);
+
+    let (playlist_id, remove) = match entry.and_then(|entry| entry.spotify_id.clone()) {
+        Some(playlist_id) if cleared => (playlist_id, Vec::new()),
+        // Stopped while the tracks of the replaced playlist were removed
+        Some(playlist_id) => {
+            let remove = spotify.get_playlist_tracks(&playlist_id).await?;
+            (playlist_id, remove)
+        }
+        None => {
+            let planned = PlaylistPlan::new(spotify, existing, playlist.clone()).await?;
+            let playlist_id = match planned.action {
+                PlaylistAction::Create => spotify.create_playlist(user_id, &playlist.title).await?,
+                PlaylistAction::Replace { spotify_id, .. } => spotify_id,
+            };
+            journal.update(id, title, |entry| {
+                entry.spotify_id = Some(playlist_id.clone())
+            })?;
+            (playlist_id, planned.remove)
+        }
+    };
+
+    if !cleared {
+        let uris = remove
+            .iter()
+            .map(SpotifyTrack::uri)
+            .collect::<Vec<String>>();
+        spotify.remove_tracks(&playlist_id, &uris).await?;
+        journal.update(id, title, |entry| entry.cleared = true)?;
+    }
+
+    let uris = playlist
+        .tracks
+        .iter()
+        .skip(added)
+        .map(SpotifyTrack::uri)
+        .collect::<Vec<String>>();
+
+    for batch in uris.chunks(TRACKS_PER_REQUEST) {
+        spotify.add_tracks(&playlist_id, batch).await?;
+        journal.update(id, title, |entry| entry.added += batch.len())?;
+    }
+
+    journal.update(id, title, |entry| entry.done = true)?;
+
+    let action = if existing.iter().any(|p| p.id == playlist_id) {
+        "Replaced"
+    } else {
+        "Created"
+    };
+    log!(
+        "Spotify",
+        LogCategory::Success,
+        { playlist: playlist.title },
+        "{action} playlist \"{}\"",
+        playlist.title
+    );
+
+    Ok(counts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Outcome;
+
+    #[test]
+    fn outcome_redacts_the_error() {
+        let outcome = Outcome::new(
+            "Road trip".to_owned(),
+            Err("error sending request for url (https://api.deezer.com/playlist/1/tracks?access_token=frAbC123&limit=100): connection reset".to_owned()),
+        );
+
+        assert_eq!(
+            outcome.result.unwrap_err(),
+            "error sending request for url (https://api.deezer.com/playlist/1/tracks?access_token=[redacted]&limit=100): connection reset"
+        );
+    }
+}